@@ -1,12 +1,19 @@
-use std::str;
+use std::borrow::Cow;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::mem;
 
+use serde::de::value::{BorrowedStrDeserializer, CowStrDeserializer};
 use serde::de::{
-    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
-    VariantAccess, Visitor,
+    self, Deserialize, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess,
+    SeqAccess, VariantAccess, Visitor,
 };
 
-use crate::error::{Error, Result};
-use crate::parser;
+use crate::encoding::{self, Encoding};
+use crate::error::{Error, ErrorKind, Result};
+use crate::parser::{self, ParseOptions, Parser};
+use crate::path::PathSegment;
+use crate::spanned;
 use crate::value::Value;
 
 /// Parse a `serde_tjs::Value` from tjs2 text.
@@ -14,41 +21,841 @@ pub fn parse_value(input: &str) -> Result<Value> {
     parser::parse_str(input)
 }
 
+/// Parse a `serde_tjs::Value` from tjs2 text with custom [`ParseOptions`].
+pub fn parse_value_with_options(input: &str, options: &ParseOptions) -> Result<Value> {
+    parser::parse_str_with_options(input, options)
+}
+
+/// Parse a `serde_tjs::Value` from tjs2 text with custom [`ParseOptions`],
+/// also returning every duplicate dictionary key that was found.
+///
+/// Duplicate keys are resolved according to
+/// [`ParseOptions::duplicate_keys`] and reported as errors of kind
+/// [`ErrorKind::DuplicateKey`], pointing at the repeated key. With
+/// [`DuplicateKeys::Error`](crate::DuplicateKeys::Error) the first duplicate
+/// is returned as an error instead.
+pub fn parse_value_with_duplicates(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<(Value, Vec<Error>)> {
+    parser::parse_str_with_duplicates(input, options)
+}
+
+/// Parse a `serde_tjs::Value` from tjs2 text, collecting every syntax error
+/// instead of stopping at the first one.
+///
+/// After an error the parser skips ahead to the next `,`, `]` or `%[` and
+/// carries on. Array elements and dictionary values that could not be read
+/// are replaced by [`Value::Void`] in the returned value, and the errors are
+/// returned in the order they were found. The input is well-formed exactly
+/// when the list of errors is empty.
+pub fn parse_value_recovering(input: &str) -> (Value, Vec<Error>) {
+    parser::parse_str_recovering(input)
+}
+
+/// Parse a `serde_tjs::Value` from bytes of tjs2 text.
+///
+/// UTF-8, UTF-16LE and UTF-16BE input is recognized by its byte order mark;
+/// input without one is read as UTF-8.
+pub fn parse_value_from_slice(input: &[u8]) -> Result<Value> {
+    parse_value_from_slice_with_options(input, &ParseOptions::default())
+}
+
+/// Parse a `serde_tjs::Value` from bytes of tjs2 text, decoding input without
+/// a byte order mark as `encoding`.
+pub fn parse_value_from_slice_with_encoding(input: &[u8], encoding: Encoding) -> Result<Value> {
+    let options = ParseOptions {
+        encoding,
+        ..ParseOptions::default()
+    };
+    parse_value_from_slice_with_options(input, &options)
+}
+
+/// Parse a `serde_tjs::Value` from bytes of tjs2 text with custom
+/// [`ParseOptions`], decoding input without a byte order mark as
+/// [`ParseOptions::encoding`].
+pub fn parse_value_from_slice_with_options(input: &[u8], options: &ParseOptions) -> Result<Value> {
+    parser::check_input_len(input.len(), options.max_input_len)?;
+    let text = encoding::decode(input, options.encoding, options.max_input_len)?;
+    parser::parse_checked(&text, options).map(|(value, _)| value)
+}
+
+/// Parse a `serde_tjs::Value` from an I/O stream of tjs2 text.
+///
+/// Parsing is not incremental: the whole stream is read into memory first,
+/// up to the default [`ParseOptions::max_input_len`], and then parsed like
+/// [`parse_value_from_slice`].
+pub fn parse_value_from_reader<R>(reader: R) -> Result<Value>
+where
+    R: Read,
+{
+    parse_value_from_reader_with_options(reader, &ParseOptions::default())
+}
+
+/// Parse a `serde_tjs::Value` from an I/O stream of tjs2 text with custom
+/// [`ParseOptions`].
+///
+/// The whole stream is read into memory before parsing starts. At most
+/// [`ParseOptions::max_input_len`] bytes are read from `reader`; without that
+/// limit the buffer grows with the input.
+pub fn parse_value_from_reader_with_options<R>(reader: R, options: &ParseOptions) -> Result<Value>
+where
+    R: Read,
+{
+    let bytes = read_to_end(reader, options.max_input_len)?;
+    parse_value_from_slice_with_options(&bytes, options)
+}
+
 /// Deserialize an instance of type `T` from a string of tjs2 text.
-pub fn from_str<T>(input: &str) -> Result<T>
+///
+/// String literals without escape sequences are borrowed from `input`, so `T`
+/// may contain `&str` or `Cow<str>` fields.
+pub fn from_str<'a, T>(input: &'a str) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    from_str_with_options(input, &ParseOptions::default())
+}
+
+/// Deserialize an instance of type `T` from a string of tjs2 text with custom
+/// [`ParseOptions`].
+pub fn from_str_with_options<'a, T>(input: &'a str, options: &ParseOptions) -> Result<T>
 where
+    T: Deserialize<'a>,
+{
+    parser::check_input_len(input.len(), options.max_input_len)?;
+    from_stream(Stream::<Borrowed>::new(input, options))
+}
+
+/// Deserialize an instance of type `T` from bytes of tjs2 text.
+///
+/// UTF-8, UTF-16LE and UTF-16BE input is recognized by its byte order mark;
+/// input without one is read as UTF-8.
+pub fn from_slice<'a, T>(input: &'a [u8]) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    from_slice_with_encoding(input, Encoding::Utf8)
+}
+
+/// Deserialize an instance of type `T` from bytes of tjs2 text, decoding input
+/// without a byte order mark as `encoding`.
+///
+/// Strings in input that has to be transcoded cannot be borrowed, so `&str`
+/// fields can only be filled from UTF-8 input.
+pub fn from_slice_with_encoding<'a, T>(input: &'a [u8], encoding: Encoding) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    let options = ParseOptions {
+        encoding,
+        ..ParseOptions::default()
+    };
+    from_slice_with_options(input, &options)
+}
+
+/// Deserialize an instance of type `T` from bytes of tjs2 text with custom
+/// [`ParseOptions`], decoding input without a byte order mark as
+/// [`ParseOptions::encoding`].
+///
+/// Strings in input that has to be transcoded cannot be borrowed, so `&str`
+/// fields can only be filled from UTF-8 input.
+pub fn from_slice_with_options<'a, T>(input: &'a [u8], options: &ParseOptions) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    parser::check_input_len(input.len(), options.max_input_len)?;
+    match encoding::decode(input, options.encoding, options.max_input_len)? {
+        Cow::Borrowed(text) => from_stream(Stream::<Borrowed>::new(text, options)),
+        Cow::Owned(text) => from_stream(Stream::<Transient>::new(&text, options)),
+    }
+}
+
+/// Deserialize an instance of type `T` from an I/O stream of tjs2 text.
+///
+/// Deserialization is not incremental: the whole stream is read into memory
+/// first, up to the default [`ParseOptions::max_input_len`], and then decoded
+/// like [`from_slice`]. Failures to read from `reader` and input that cannot be
+/// decoded are reported as distinct errors.
+pub fn from_reader<R, T>(reader: R) -> Result<T>
+where
+    R: Read,
     T: DeserializeOwned,
 {
-    let value = parse_value(input)?;
-    from_value(value)
+    from_reader_with_options(reader, &ParseOptions::default())
 }
 
-/// Deserialize an instance of type `T`` from bytes of tjs2 text.
-pub fn from_slice<T>(input: &[u8]) -> Result<T>
+/// Deserialize an instance of type `T` from an I/O stream of tjs2 text with
+/// custom [`ParseOptions`].
+///
+/// The whole stream is read into memory before deserialization starts. At
+/// most [`ParseOptions::max_input_len`] bytes are read from `reader`; without
+/// that limit the buffer grows with the input.
+pub fn from_reader_with_options<R, T>(reader: R, options: &ParseOptions) -> Result<T>
 where
+    R: Read,
     T: DeserializeOwned,
 {
-    let text = str::from_utf8(input)
-        .map_err(|err| Error::new(format!("input is not valid UTF-8: {err}")))?;
-    from_str(text)
+    let bytes = read_to_end(reader, options.max_input_len)?;
+    let text = encoding::decode(&bytes, options.encoding, options.max_input_len)?;
+    from_stream(Stream::<Borrowed>::new(&text, options))
+}
+
+/// Reads all of `reader`, failing once more than `max_len` bytes were read.
+fn read_to_end<R>(reader: R, max_len: Option<usize>) -> Result<Vec<u8>>
+where
+    R: Read,
+{
+    // One byte past the limit is enough to tell that the input is too large.
+    let limit = max_len.map_or(u64::MAX, |len| len as u64 + 1);
+    let mut buffer = Vec::new();
+    reader
+        .take(limit)
+        .read_to_end(&mut buffer)
+        .map_err(Error::io)?;
+    parser::check_input_len(buffer.len(), max_len)?;
+    Ok(buffer)
 }
 
 /// Interpret a `serde_tjs::Value` as an instance of type `T`.
-pub fn from_value<T>(value: Value) -> Result<T>
+///
+/// The value is consumed, so strings are always handed out as owned data:
+/// `Cow<str>` fields work, but `&str` fields need [`from_str`].
+pub fn from_value<'de, T>(value: Value) -> Result<T>
 where
-    T: DeserializeOwned,
+    T: Deserialize<'de>,
 {
     T::deserialize(ValueDeserializer::new(value))
 }
 
+/// A deserializer that reads TJS2 text directly, without building an
+/// intermediate [`Value`].
+pub struct Deserializer<'de> {
+    stream: Stream<'de, Borrowed>,
+}
+
+impl<'de> Deserializer<'de> {
+    /// Creates a deserializer reading from a string of tjs2 text.
+    pub fn new(input: &'de str) -> Self {
+        Self {
+            stream: Stream::new(input, &ParseOptions::default()),
+        }
+    }
+
+    /// Creates a deserializer reading from a string of tjs2 text with custom
+    /// [`ParseOptions`], failing if `input` is larger than
+    /// [`ParseOptions::max_input_len`].
+    pub fn with_options(input: &'de str, options: &ParseOptions) -> Result<Self> {
+        parser::check_input_len(input.len(), options.max_input_len)?;
+        Ok(Self {
+            stream: Stream::new(input, options),
+        })
+    }
+
+    /// Checks that only whitespace and comments remain in the input.
+    ///
+    /// This should be called after deserializing a value to make sure the
+    /// whole input has been consumed.
+    pub fn end(&mut self) -> Result<()> {
+        self.stream.end()
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.stream.deserialize_any(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.stream.deserialize_option(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.stream.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.stream.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.stream.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.stream.deserialize_ignored_any(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map identifier
+    }
+}
+
+/// Decides how strings read from input that lives for `'i` are handed to a
+/// visitor for `'de`.
+trait Strings<'i, 'de> {
+    fn deserialize<S>(seed: S, text: Cow<'i, str>) -> Result<S::Value>
+    where
+        S: DeserializeSeed<'de>;
+}
+
+/// Strings without escape sequences are borrowed from the input.
+struct Borrowed;
+
+impl<'de> Strings<'de, 'de> for Borrowed {
+    fn deserialize<S>(seed: S, text: Cow<'de, str>) -> Result<S::Value>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        match text {
+            Cow::Borrowed(text) => seed.deserialize(BorrowedStrDeserializer::new(text)),
+            Cow::Owned(text) => seed.deserialize(text.into_deserializer()),
+        }
+    }
+}
+
+/// The input does not outlive the deserializer, such as text decoded from
+/// UTF-16, so strings are never borrowed.
+struct Transient;
+
+impl<'de> Strings<'_, 'de> for Transient {
+    fn deserialize<S>(seed: S, text: Cow<'_, str>) -> Result<S::Value>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        seed.deserialize(CowStrDeserializer::new(text))
+    }
+}
+
+/// Hands a string to a visitor through [`Strings::deserialize`].
+struct VisitStr<V>(V);
+
+impl<'de, V: Visitor<'de>> DeserializeSeed<'de> for VisitStr<V> {
+    type Value = V::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<V::Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self.0)
+    }
+}
+
+/// Hands a unit variant name to a visitor through [`Strings::deserialize`].
+struct VisitVariant<V>(V);
+
+impl<'de, V: Visitor<'de>> DeserializeSeed<'de> for VisitVariant<V> {
+    type Value = V::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<V::Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_enum("", &[], self.0)
+    }
+}
+
+/// The streaming deserializer behind [`Deserializer`], reading input that
+/// lives for `'i` and handing out strings as decided by `M`.
+struct Stream<'i, M> {
+    parser: Parser<'i>,
+    strings: PhantomData<M>,
+}
+
+/// Deserializes a `T` from the whole of `stream`.
+fn from_stream<'i, 'de, M, T>(mut stream: Stream<'i, M>) -> Result<T>
+where
+    M: Strings<'i, 'de>,
+    T: Deserialize<'de>,
+{
+    let value = stream.deserialize_located(PhantomData)?;
+    stream.end()?;
+    Ok(value)
+}
+
+impl<'i, M> Stream<'i, M> {
+    fn new(input: &'i str, options: &ParseOptions) -> Self {
+        Self {
+            parser: Parser::with_options(input, options),
+            strings: PhantomData,
+        }
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.parser.skip_ws()?;
+        if self.parser.is_eof() {
+            Ok(())
+        } else {
+            Err(self
+                .parser
+                .error(ErrorKind::Syntax, "unexpected trailing characters"))
+        }
+    }
+
+    /// Deserializes the value at the current position, pointing errors that
+    /// carry no location of their own at the start of that value.
+    fn deserialize_located<'de, S>(&mut self, seed: S) -> Result<S::Value>
+    where
+        M: Strings<'i, 'de>,
+        S: DeserializeSeed<'de>,
+    {
+        self.located(|de| seed.deserialize(de))
+    }
+
+    fn located<T, F>(&mut self, read: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        self.parser.skip_ws()?;
+        let start = self.parser.position();
+        read(self).map_err(|err| self.parser.locate(err, start))
+    }
+
+    fn peek_value(&mut self) -> Result<u8> {
+        self.parser
+            .peek_value_start()?
+            .ok_or_else(|| self.parser.error(ErrorKind::Eof, "unexpected end of input"))
+    }
+
+    fn parse_scalar(&mut self) -> Result<Value> {
+        match self.peek_value()? {
+            b'"' | b'\'' => self
+                .parser
+                .parse_string()
+                .map(|text| Value::String(text.into_owned())),
+            b'<' => self.parser.parse_octet(),
+            b't' | b'f' | b'n' | b'v' | b'I' | b'N' => self.parser.parse_literal(),
+            b'+' | b'-' | b'0'..=b'9' => self.parser.parse_number(),
+            _ => Err(self.parser.error(ErrorKind::Syntax, "unexpected token")),
+        }
+    }
+
+    fn begin_dictionary(&mut self) -> Result<()> {
+        self.parser.expect_char('%')?;
+        self.parser.skip_ws()?;
+        self.parser.expect_char('[')
+    }
+
+    fn end_container(&mut self) -> Result<()> {
+        self.parser.skip_ws()?;
+        if self.parser.consume_ascii(',') {
+            self.parser.skip_ws()?;
+        }
+        self.parser.expect_char(']')
+    }
+
+    fn parse_key_separator(&mut self) -> Result<()> {
+        self.parser.skip_ws()?;
+        if self.parser.consume_exact("=>") || self.parser.consume_ascii(':') {
+            Ok(())
+        } else {
+            Err(self
+                .parser
+                .error(ErrorKind::Syntax, "expected '=>' after key"))
+        }
+    }
+}
+
+impl<'i, 'de, M: Strings<'i, 'de>> de::Deserializer<'de> for &mut Stream<'i, M> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek_value()? {
+            b'[' => {
+                self.parser.enter()?;
+                self.parser.expect_char('[')?;
+                let value = visitor.visit_seq(StreamSeqAccess::new(self))?;
+                self.end_container()?;
+                self.parser.leave();
+                Ok(value)
+            }
+            b'%' => {
+                self.parser.enter()?;
+                self.begin_dictionary()?;
+                let value = visitor.visit_map(StreamMapAccess::new(self))?;
+                self.end_container()?;
+                self.parser.leave();
+                Ok(value)
+            }
+            b'"' | b'\'' => M::deserialize(VisitStr(visitor), self.parser.parse_string()?),
+            _ => match &mut self.parse_scalar()? {
+                Value::Void | Value::Null => visitor.visit_unit(),
+                Value::Bool(v) => visitor.visit_bool(*v),
+                Value::Integer(v) => visitor.visit_i64(*v),
+                Value::Real(v) => visitor.visit_f64(*v),
+                Value::String(v) => visitor.visit_string(mem::take(v)),
+                Value::Octet(v) => visitor.visit_byte_buf(mem::take(v)),
+                Value::Array(_) | Value::Dictionary(_) => unreachable!(),
+            },
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if matches!(self.peek_value()?, b'v' | b'n') {
+            match self.parser.parse_literal()? {
+                Value::Void | Value::Null => visitor.visit_none(),
+                other => Err(Error::new(
+                    ErrorKind::TypeMismatch,
+                    format!("expected option, found {other:?}"),
+                )),
+            }
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek_value()? {
+            b'"' | b'\'' => M::deserialize(VisitVariant(visitor), self.parser.parse_string()?),
+            b'%' => {
+                self.parser.enter()?;
+                self.begin_dictionary()?;
+                self.parser.skip_ws()?;
+                let value = visitor.visit_enum(StreamEnumAccess {
+                    de: &mut *self,
+                    name: Cow::Borrowed(""),
+                })?;
+                self.end_container()?;
+                self.parser.leave();
+                Ok(value)
+            }
+            _ => Err(self.parser.error(ErrorKind::TypeMismatch, "expected enum")),
+        }
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if name == spanned::NAME && fields == spanned::FIELDS {
+            self.peek_value()?;
+            let start = self.parser.position();
+            let (line, column) = self.parser.line_column(start);
+            visitor.visit_map(SpannedAccess {
+                de: self,
+                start,
+                line,
+                column,
+                field: 0,
+            })
+        } else {
+            self.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.parser.skip_value()?;
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map identifier
+    }
+}
+
+struct StreamSeqAccess<'a, 'i, M> {
+    de: &'a mut Stream<'i, M>,
+    /// Number of elements read so far.
+    len: usize,
+}
+
+impl<'a, 'i, M> StreamSeqAccess<'a, 'i, M> {
+    fn new(de: &'a mut Stream<'i, M>) -> Self {
+        Self { de, len: 0 }
+    }
+}
+
+impl<'i, 'de, M: Strings<'i, 'de>> SeqAccess<'de> for StreamSeqAccess<'_, 'i, M> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if !next_entry(self.de, &mut self.len)? {
+            return Ok(None);
+        }
+        let index = self.len - 1;
+        self.de
+            .deserialize_located(seed)
+            .map(Some)
+            .map_err(|err| err.prepend_path(PathSegment::Index(index)))
+    }
+}
+
+struct StreamMapAccess<'a, 'i, M> {
+    de: &'a mut Stream<'i, M>,
+    /// Number of entries read so far.
+    len: usize,
+    /// The key of the entry being read.
+    key: Option<Cow<'i, str>>,
+}
+
+impl<'a, 'i, M> StreamMapAccess<'a, 'i, M> {
+    fn new(de: &'a mut Stream<'i, M>) -> Self {
+        Self {
+            de,
+            len: 0,
+            key: None,
+        }
+    }
+}
+
+impl<'i, 'de, M: Strings<'i, 'de>> MapAccess<'de> for StreamMapAccess<'_, 'i, M> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if !next_entry(self.de, &mut self.len)? {
+            return Ok(None);
+        }
+        let start = self.de.parser.position();
+        let key = self.de.parser.parse_dict_key()?;
+        self.key = Some(key.clone());
+        M::deserialize(seed, key)
+            .map(Some)
+            .map_err(|err| self.de.parser.locate(err, start))
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        self.de.parse_key_separator()?;
+        let key = self.key.take();
+        self.de.deserialize_located(seed).map_err(|err| match key {
+            Some(key) => err.prepend_path(PathSegment::Key(key.into_owned())),
+            None => err,
+        })
+    }
+}
+
+/// Hands the location of a value to [`Spanned`](crate::Spanned), together
+/// with the value itself.
+struct SpannedAccess<'a, 'i, M> {
+    de: &'a mut Stream<'i, M>,
+    start: usize,
+    line: usize,
+    column: usize,
+    field: usize,
+}
+
+impl<'i, 'de, M: Strings<'i, 'de>> MapAccess<'de> for SpannedAccess<'_, 'i, M> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let Some(field) = spanned::FIELDS.get(self.field) else {
+            return Ok(None);
+        };
+        self.field += 1;
+        seed.deserialize(BorrowedStrDeserializer::new(field))
+            .map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        match spanned::FIELDS[self.field - 1] {
+            spanned::START => seed.deserialize(self.start.into_deserializer()),
+            spanned::LINE => seed.deserialize(self.line.into_deserializer()),
+            spanned::COLUMN => seed.deserialize(self.column.into_deserializer()),
+            spanned::VALUE => seed.deserialize(&mut *self.de),
+            _ => seed.deserialize(self.de.parser.position().into_deserializer()),
+        }
+    }
+}
+
+/// Moves past the separator in front of the next array element or dictionary
+/// entry, returning `false` once the closing bracket is reached. `len` counts
+/// the entries read so far.
+fn next_entry<M>(de: &mut Stream<'_, M>, len: &mut usize) -> Result<bool> {
+    de.parser.skip_ws()?;
+    if de.parser.peek_byte() == Some(b']') {
+        return Ok(false);
+    }
+    if *len > 0 {
+        if !de.parser.consume_ascii(',') {
+            return Err(de.parser.error(ErrorKind::Syntax, "expected ',' or ']'"));
+        }
+        de.parser.skip_ws()?;
+        if de.parser.peek_byte() == Some(b']') {
+            return Ok(false);
+        }
+    }
+    *len += 1;
+    de.parser.add_element(*len)?;
+    Ok(true)
+}
+
+struct StreamEnumAccess<'a, 'i, M> {
+    de: &'a mut Stream<'i, M>,
+    /// The name of the variant, once it has been read.
+    name: Cow<'i, str>,
+}
+
+impl<'i, M> StreamEnumAccess<'_, 'i, M> {
+    /// Reads the content of the variant, attributing errors to it.
+    fn content<T, F>(self, read: F) -> Result<T>
+    where
+        F: FnOnce(&mut Stream<'i, M>) -> Result<T>,
+    {
+        let name = self.name;
+        self.de
+            .located(read)
+            .map_err(|err| err.prepend_path(PathSegment::Key(name.into_owned())))
+    }
+}
+
+impl<'i, 'de, M: Strings<'i, 'de>> EnumAccess<'de> for StreamEnumAccess<'_, 'i, M> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let start = self.de.parser.position();
+        let name = self.de.parser.parse_dict_key()?;
+        let variant =
+            M::deserialize(seed, name.clone()).map_err(|err| self.de.parser.locate(err, start))?;
+        self.de.parse_key_separator()?;
+        Ok((variant, StreamEnumAccess { name, ..self }))
+    }
+}
+
+impl<'i, 'de, M: Strings<'i, 'de>> VariantAccess<'de> for StreamEnumAccess<'_, 'i, M> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        self.content(|de| Deserialize::deserialize(de))
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.content(|de| seed.deserialize(de))
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.content(|de| de::Deserializer::deserialize_seq(de, visitor))
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.content(|de| de::Deserializer::deserialize_map(de, visitor))
+    }
+}
+
 pub struct ValueDeserializer {
     value: Value,
+    /// How many more levels of arrays and dictionaries may be entered.
+    remaining_depth: Option<usize>,
 }
 
 impl ValueDeserializer {
     pub fn new(value: Value) -> Self {
-        Self { value }
+        Self::with_depth(value, ParseOptions::default().max_depth)
     }
+
+    fn with_depth(value: Value, remaining_depth: Option<usize>) -> Self {
+        Self {
+            value,
+            remaining_depth,
+        }
+    }
+}
+
+/// Returns the depth left for the contents of a container that is entered
+/// with `remaining_depth` levels to go.
+fn enter(remaining_depth: Option<usize>) -> Result<Option<usize>> {
+    match remaining_depth {
+        Some(0) => Err(Error::new(
+            ErrorKind::DepthLimitExceeded,
+            "nesting depth limit exceeded",
+        )),
+        remaining => Ok(remaining.map(|depth| depth - 1)),
+    }
+}
+
+/// Reports that `found` is not of the `expected` type.
+fn mismatch(expected: &str, found: &Value) -> Error {
+    Error::new(
+        ErrorKind::TypeMismatch,
+        format!("expected {expected}, found {}", found.kind()),
+    )
 }
 
 impl<'de> IntoDeserializer<'de, Error> for Value {
@@ -62,28 +869,23 @@ impl<'de> IntoDeserializer<'de, Error> for Value {
 impl<'de> de::Deserializer<'de> for ValueDeserializer {
     type Error = Error;
 
-    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_any<V>(mut self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.value {
+        match &mut self.value {
             Value::Void | Value::Null => visitor.visit_unit(),
-            Value::Bool(v) => visitor.visit_bool(v),
-            Value::Integer(v) => visitor.visit_i64(v),
-            Value::Real(v) => visitor.visit_f64(v),
-            Value::String(v) => visitor.visit_string(v),
-            Value::Octet(v) => visitor.visit_byte_buf(v),
+            Value::Bool(v) => visitor.visit_bool(*v),
+            Value::Integer(v) => visitor.visit_i64(*v),
+            Value::Real(v) => visitor.visit_f64(*v),
+            Value::String(v) => visitor.visit_string(mem::take(v)),
+            Value::Octet(v) => visitor.visit_byte_buf(mem::take(v)),
             Value::Array(values) => {
-                let seq = SeqDeserializer {
-                    iter: values.into_iter(),
-                };
+                let seq = SeqDeserializer::new(mem::take(values), self.remaining_depth)?;
                 visitor.visit_seq(seq)
             }
             Value::Dictionary(map) => {
-                let map = MapDeserializer {
-                    iter: map.into_iter(),
-                    value: None,
-                };
+                let map = MapDeserializer::new(mem::take(map), self.remaining_depth)?;
                 visitor.visit_map(map)
             }
         }
@@ -95,7 +897,7 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     {
         match self.value {
             Value::Bool(v) => visitor.visit_bool(v),
-            other => Err(Error::new(format!("expected bool, found {other:?}"))),
+            other => Err(mismatch("bool", &other)),
         }
     }
 
@@ -126,7 +928,7 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     {
         match self.value {
             Value::Integer(v) => visitor.visit_i64(v),
-            other => Err(Error::new(format!("expected integer, found {other:?}"))),
+            other => Err(mismatch("integer", &other)),
         }
     }
 
@@ -136,7 +938,7 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     {
         match self.value {
             Value::Integer(v) => visitor.visit_i128(v as i128),
-            other => Err(Error::new(format!("expected integer, found {other:?}"))),
+            other => Err(mismatch("integer", &other)),
         }
     }
 
@@ -167,9 +969,7 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     {
         match self.value {
             Value::Integer(v) if v >= 0 => visitor.visit_u64(v as u64),
-            other => Err(Error::new(format!(
-                "expected unsigned integer, found {other:?}"
-            ))),
+            other => Err(mismatch("unsigned integer", &other)),
         }
     }
 
@@ -179,9 +979,7 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     {
         match self.value {
             Value::Integer(v) if v >= 0 => visitor.visit_u128(v as u128),
-            other => Err(Error::new(format!(
-                "expected unsigned integer, found {other:?}"
-            ))),
+            other => Err(mismatch("unsigned integer", &other)),
         }
     }
 
@@ -199,7 +997,7 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
         match self.value {
             Value::Real(v) => visitor.visit_f64(v),
             Value::Integer(v) => visitor.visit_f64(v as f64),
-            other => Err(Error::new(format!("expected float, found {other:?}"))),
+            other => Err(mismatch("float", &other)),
         }
     }
 
@@ -207,30 +1005,36 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     where
         V: Visitor<'de>,
     {
-        match self.value {
+        match &self.value {
             Value::String(s) => {
                 let mut chars = s.chars();
                 if let Some(ch) = chars.next() {
                     if chars.next().is_none() {
                         visitor.visit_char(ch)
                     } else {
-                        Err(Error::new("expected single character"))
+                        Err(Error::new(
+                            ErrorKind::TypeMismatch,
+                            "expected single character",
+                        ))
                     }
                 } else {
-                    Err(Error::new("expected single character"))
+                    Err(Error::new(
+                        ErrorKind::TypeMismatch,
+                        "expected single character",
+                    ))
                 }
             }
-            other => Err(Error::new(format!("expected char, found {other:?}"))),
+            other => Err(mismatch("char", other)),
         }
     }
 
-    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_str<V>(mut self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.value {
-            Value::String(v) => visitor.visit_string(v),
-            other => Err(Error::new(format!("expected string, found {other:?}"))),
+        match &mut self.value {
+            Value::String(v) => visitor.visit_string(mem::take(v)),
+            other => Err(mismatch("string", other)),
         }
     }
 
@@ -241,14 +1045,14 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
         self.deserialize_str(visitor)
     }
 
-    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_bytes<V>(mut self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.value {
-            Value::Octet(v) => visitor.visit_byte_buf(v),
-            Value::String(s) => visitor.visit_byte_buf(s.into_bytes()),
-            other => Err(Error::new(format!("expected byte buffer, found {other:?}"))),
+        match &mut self.value {
+            Value::Octet(v) => visitor.visit_byte_buf(mem::take(v)),
+            Value::String(s) => visitor.visit_byte_buf(mem::take(s).into_bytes()),
+            other => Err(mismatch("byte buffer", other)),
         }
     }
 
@@ -265,7 +1069,7 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     {
         match self.value {
             Value::Void | Value::Null => visitor.visit_none(),
-            other => visitor.visit_some(ValueDeserializer::new(other)),
+            other => visitor.visit_some(ValueDeserializer::with_depth(other, self.remaining_depth)),
         }
     }
 
@@ -275,7 +1079,7 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     {
         match self.value {
             Value::Void | Value::Null => visitor.visit_unit(),
-            other => Err(Error::new(format!("expected unit, found {other:?}"))),
+            other => Err(mismatch("unit", &other)),
         }
     }
 
@@ -290,21 +1094,19 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_newtype_struct(ValueDeserializer::new(self.value))
+        visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_seq<V>(mut self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.value {
+        match &mut self.value {
             Value::Array(values) => {
-                let seq = SeqDeserializer {
-                    iter: values.into_iter(),
-                };
+                let seq = SeqDeserializer::new(mem::take(values), self.remaining_depth)?;
                 visitor.visit_seq(seq)
             }
-            other => Err(Error::new(format!("expected array, found {other:?}"))),
+            other => Err(mismatch("array", other)),
         }
     }
 
@@ -327,36 +1129,39 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
         self.deserialize_seq(visitor)
     }
 
-    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_map<V>(mut self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.value {
+        match &mut self.value {
             Value::Dictionary(map) => {
-                let access = MapDeserializer {
-                    iter: map.into_iter(),
-                    value: None,
-                };
+                let access = MapDeserializer::new(mem::take(map), self.remaining_depth)?;
                 visitor.visit_map(access)
             }
-            other => Err(Error::new(format!("expected dictionary, found {other:?}"))),
+            other => Err(mismatch("dictionary", other)),
         }
     }
 
     fn deserialize_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         _fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        if name == spanned::NAME {
+            return Err(Error::new(
+                ErrorKind::Custom,
+                "Spanned values can only be deserialized from TJS2 text",
+            ));
+        }
         self.deserialize_map(visitor)
     }
 
     fn deserialize_enum<V>(
-        self,
+        mut self,
         _name: &'static str,
         _variants: &'static [&'static str],
         visitor: V,
@@ -364,24 +1169,28 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
     where
         V: Visitor<'de>,
     {
-        match self.value {
+        match &mut self.value {
             Value::String(name) => visitor.visit_enum(EnumDeserializer {
-                variant: name,
+                variant: mem::take(name),
                 value: None,
+                remaining_depth: self.remaining_depth,
             }),
             Value::Dictionary(map) => {
                 if map.len() != 1 {
                     return Err(Error::new(
+                        ErrorKind::TypeMismatch,
                         "enum representation must contain exactly one entry",
                     ));
                 }
-                let (name, value) = map.into_iter().next().unwrap();
+                let remaining_depth = enter(self.remaining_depth)?;
+                let (name, value) = map.pop().unwrap();
                 visitor.visit_enum(EnumDeserializer {
                     variant: name,
                     value: Some(value),
+                    remaining_depth,
                 })
             }
-            other => Err(Error::new(format!("expected enum, found {other:?}"))),
+            other => Err(mismatch("enum", other)),
         }
     }
 
@@ -402,6 +1211,21 @@ impl<'de> de::Deserializer<'de> for ValueDeserializer {
 
 struct SeqDeserializer {
     iter: std::vec::IntoIter<Value>,
+    index: usize,
+    remaining_depth: Option<usize>,
+}
+
+impl SeqDeserializer {
+    /// Enters an array that is reached with `remaining_depth` levels to go.
+    fn new(values: Vec<Value>, remaining_depth: Option<usize>) -> Result<Self> {
+        let mut seq = Self {
+            iter: values.into_iter(),
+            index: 0,
+            remaining_depth,
+        };
+        seq.remaining_depth = enter(remaining_depth)?;
+        Ok(seq)
+    }
 }
 
 impl<'de> SeqAccess<'de> for SeqDeserializer {
@@ -411,16 +1235,36 @@ impl<'de> SeqAccess<'de> for SeqDeserializer {
     where
         T: DeserializeSeed<'de>,
     {
-        match self.iter.next() {
-            Some(value) => seed.deserialize(ValueDeserializer::new(value)).map(Some),
-            None => Ok(None),
-        }
+        let Some(value) = self.iter.next() else {
+            return Ok(None);
+        };
+        let index = self.index;
+        self.index += 1;
+        seed.deserialize(ValueDeserializer::with_depth(value, self.remaining_depth))
+            .map(Some)
+            .map_err(|err| err.prepend_path(PathSegment::Index(index)))
     }
 }
 
 struct MapDeserializer {
     iter: indexmap::map::IntoIter<String, Value>,
-    value: Option<Value>,
+    /// The key and value of the entry being read.
+    entry: Option<(String, Value)>,
+    remaining_depth: Option<usize>,
+}
+
+impl MapDeserializer {
+    /// Enters a dictionary that is reached with `remaining_depth` levels to
+    /// go.
+    fn new(map: indexmap::IndexMap<String, Value>, remaining_depth: Option<usize>) -> Result<Self> {
+        let mut access = Self {
+            iter: map.into_iter(),
+            entry: None,
+            remaining_depth,
+        };
+        access.remaining_depth = enter(remaining_depth)?;
+        Ok(access)
+    }
 }
 
 impl<'de> MapAccess<'de> for MapDeserializer {
@@ -432,8 +1276,8 @@ impl<'de> MapAccess<'de> for MapDeserializer {
     {
         match self.iter.next() {
             Some((key, value)) => {
-                self.value = Some(value);
-                let key_deserializer = ValueDeserializer::new(Value::String(key));
+                let key_deserializer = ValueDeserializer::new(Value::String(key.clone()));
+                self.entry = Some((key, value));
                 seed.deserialize(key_deserializer).map(Some)
             }
             None => Ok(None),
@@ -444,17 +1288,19 @@ impl<'de> MapAccess<'de> for MapDeserializer {
     where
         V: DeserializeSeed<'de>,
     {
-        let value = self
-            .value
+        let (key, value) = self
+            .entry
             .take()
-            .ok_or_else(|| Error::new("value missing for key"))?;
-        seed.deserialize(ValueDeserializer::new(value))
+            .ok_or_else(|| Error::new(ErrorKind::Custom, "value missing for key"))?;
+        seed.deserialize(ValueDeserializer::with_depth(value, self.remaining_depth))
+            .map_err(|err| err.prepend_path(PathSegment::Key(key)))
     }
 }
 
 struct EnumDeserializer {
     variant: String,
     value: Option<Value>,
+    remaining_depth: Option<usize>,
 }
 
 impl<'de> EnumAccess<'de> for EnumDeserializer {
@@ -465,13 +1311,28 @@ impl<'de> EnumAccess<'de> for EnumDeserializer {
     where
         V: DeserializeSeed<'de>,
     {
-        let variant = seed.deserialize(ValueDeserializer::new(Value::String(self.variant)))?;
-        Ok((variant, VariantDeserializer { value: self.value }))
+        let access = VariantDeserializer {
+            name: self.variant,
+            value: self.value,
+            remaining_depth: self.remaining_depth,
+        };
+        let name = ValueDeserializer::new(Value::String(access.name.clone()));
+        let variant = seed.deserialize(name)?;
+        Ok((variant, access))
     }
 }
 
 struct VariantDeserializer {
+    name: String,
     value: Option<Value>,
+    remaining_depth: Option<usize>,
+}
+
+impl VariantDeserializer {
+    /// Attributes an error raised while reading the variant's content to it.
+    fn locate(&self, err: Error) -> Error {
+        err.prepend_path(PathSegment::Key(self.name.clone()))
+    }
 }
 
 impl<'de> VariantAccess<'de> for VariantDeserializer {
@@ -480,48 +1341,59 @@ impl<'de> VariantAccess<'de> for VariantDeserializer {
     fn unit_variant(self) -> Result<()> {
         match self.value {
             None => Ok(()),
-            Some(_) => Err(Error::new("expected unit variant")),
+            Some(_) => {
+                Err(self.locate(Error::new(ErrorKind::TypeMismatch, "expected unit variant")))
+            }
         }
     }
 
-    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    fn newtype_variant_seed<T>(mut self, seed: T) -> Result<T::Value>
     where
         T: DeserializeSeed<'de>,
     {
-        match self.value {
-            Some(value) => seed.deserialize(ValueDeserializer::new(value)),
-            None => Err(Error::new("expected value for newtype variant")),
-        }
+        let result = match self.value.take() {
+            Some(value) => {
+                seed.deserialize(ValueDeserializer::with_depth(value, self.remaining_depth))
+            }
+            None => Err(Error::new(
+                ErrorKind::TypeMismatch,
+                "expected value for newtype variant",
+            )),
+        };
+        result.map_err(|err| self.locate(err))
     }
 
-    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    fn tuple_variant<V>(mut self, _len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.value {
+        let result = match &mut self.value {
             Some(Value::Array(values)) => {
-                let seq = SeqDeserializer {
-                    iter: values.into_iter(),
-                };
-                visitor.visit_seq(seq)
+                SeqDeserializer::new(mem::take(values), self.remaining_depth)
+                    .and_then(|seq| visitor.visit_seq(seq))
             }
-            _ => Err(Error::new("tuple variant expected an array")),
-        }
+            _ => Err(Error::new(
+                ErrorKind::TypeMismatch,
+                "tuple variant expected an array",
+            )),
+        };
+        result.map_err(|err| self.locate(err))
     }
 
-    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    fn struct_variant<V>(mut self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.value {
+        let result = match &mut self.value {
             Some(Value::Dictionary(map)) => {
-                let access = MapDeserializer {
-                    iter: map.into_iter(),
-                    value: None,
-                };
-                visitor.visit_map(access)
+                MapDeserializer::new(mem::take(map), self.remaining_depth)
+                    .and_then(|map| visitor.visit_map(map))
             }
-            _ => Err(Error::new("struct variant expected a dictionary")),
-        }
+            _ => Err(Error::new(
+                ErrorKind::TypeMismatch,
+                "struct variant expected a dictionary",
+            )),
+        };
+        result.map_err(|err| self.locate(err))
     }
 }
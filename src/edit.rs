@@ -0,0 +1,891 @@
+//! Format-preserving editing of TJS2 documents.
+//!
+//! A [`Document`] keeps the source text it was parsed from: comments,
+//! whitespace, `(const)` hints, quote styles, number spellings and `:` or `=>`
+//! separators all survive a round trip. Values can be read, replaced, inserted
+//! and removed by [`Path`], and writing the document back only changes the
+//! parts that were edited.
+//!
+//! ```
+//! use serde_tjs::edit::Document;
+//!
+//! let mut doc: Document = r#"%[
+//!     // Keep this comment.
+//!     'name' => 'John Doe',
+//!     "phones" => (const) [0x10, "+44 2345678"]
+//! ]"#
+//! .parse()?;
+//! doc.set("phones[1]", "+44 0000000")?;
+//! doc.insert("age", 43_i64)?;
+//! assert_eq!(
+//!     doc.to_string(),
+//!     r#"%[
+//!     // Keep this comment.
+//!     'name' => 'John Doe',
+//!     "phones" => (const) [0x10, "+44 0000000"],
+//!     "age" => 43
+//! ]"#
+//! );
+//! # Ok::<(), serde_tjs::Error>(())
+//! ```
+
+use std::fmt::{self, Write};
+use std::mem;
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::parser::Parser;
+use crate::path::{Path, PathSegment};
+use crate::value::{SerializeOptions, Value, write_string};
+
+/// A TJS2 document that remembers how it was written.
+#[derive(Clone)]
+pub struct Document {
+    root: Item,
+}
+
+/// A value together with the text around it.
+#[derive(Clone)]
+struct Item {
+    /// Whitespace, comments and hints in front of the value.
+    prefix: String,
+    node: Node,
+    /// Whitespace and comments between the value and the next separator.
+    suffix: String,
+}
+
+#[derive(Clone)]
+struct Entry {
+    /// Whitespace and comments in front of the key.
+    prefix: String,
+    key: String,
+    /// The key as written, or `None` for keys added by an edit.
+    key_repr: Option<String>,
+    /// The text between the key and the value, including the `=>` or `:`.
+    separator: String,
+    value: Item,
+}
+
+enum Node {
+    Scalar {
+        value: Value,
+        /// The value as written, or `None` for values set by an edit.
+        repr: Option<String>,
+    },
+    Array(Container<Item>),
+    Dictionary(Container<Entry>),
+}
+
+#[derive(Clone)]
+struct Container<T> {
+    /// The opening bracket as written (`[`, `%[` or `% [`).
+    open: String,
+    items: Vec<T>,
+    trailing_comma: bool,
+    /// Whitespace and comments in front of the closing bracket.
+    trailing: String,
+}
+
+impl Document {
+    /// Returns the whole document as a [`Value`].
+    pub fn to_value(&self) -> Value {
+        self.root.node.to_value()
+    }
+
+    /// Returns the value at `path`, or `None` if there is no such value.
+    pub fn get(&self, path: &str) -> Result<Option<Value>> {
+        let path: Path = path.parse()?;
+        Ok(self.root.node.get(path.segments()).map(Node::to_value))
+    }
+
+    /// Replaces the value at `path`, or adds it if `path` names a missing key
+    /// of an existing dictionary.
+    ///
+    /// The text around the old value is kept; the new value itself is written
+    /// out compactly.
+    pub fn set(&mut self, path: &str, value: impl Into<Value>) -> Result<()> {
+        let path: Path = path.parse()?;
+        let node = Node::from(value.into());
+        let Some((last, parent)) = path.segments().split_last() else {
+            self.root.node = node;
+            return Ok(());
+        };
+        match (self.container_mut(parent)?, last) {
+            (Node::Dictionary(dict), PathSegment::Key(key)) => {
+                match dict.items.iter().rposition(|entry| entry.key == *key) {
+                    Some(idx) => dict.items[idx].value.node = node,
+                    None => dict.append_entry(key.clone(), node),
+                }
+                Ok(())
+            }
+            (Node::Array(array), PathSegment::Index(index)) => match array.items.get_mut(*index) {
+                Some(item) => {
+                    item.node = node;
+                    Ok(())
+                }
+                None => Err(Error::new(ErrorKind::Custom, "array index out of range")
+                    .with_path(path.clone())),
+            },
+            (_, segment) => Err(mismatch(parent, segment)),
+        }
+    }
+
+    /// Inserts a value at `path`.
+    ///
+    /// For an array element, the value is inserted before the element that
+    /// currently has that index, or appended if the index equals the length of
+    /// the array. For a dictionary key, the entry is added at the end and the
+    /// key must not exist yet. New entries copy the layout of their siblings.
+    pub fn insert(&mut self, path: &str, value: impl Into<Value>) -> Result<()> {
+        let path: Path = path.parse()?;
+        let node = Node::from(value.into());
+        let Some((last, parent)) = path.segments().split_last() else {
+            return Err(Error::new(
+                ErrorKind::Custom,
+                "cannot insert the root value",
+            ));
+        };
+        match (self.container_mut(parent)?, last) {
+            (Node::Dictionary(dict), PathSegment::Key(key)) => {
+                if dict.items.iter().any(|entry| entry.key == *key) {
+                    return Err(
+                        Error::new(ErrorKind::Custom, "key already exists").with_path(path.clone())
+                    );
+                }
+                dict.append_entry(key.clone(), node);
+                Ok(())
+            }
+            (Node::Array(array), PathSegment::Index(index)) if *index <= array.items.len() => {
+                array.insert_item(*index, node);
+                Ok(())
+            }
+            (Node::Array(_), PathSegment::Index(_)) => {
+                Err(Error::new(ErrorKind::Custom, "array index out of range")
+                    .with_path(path.clone()))
+            }
+            (_, segment) => Err(mismatch(parent, segment)),
+        }
+    }
+
+    /// Removes the value at `path` together with the comments in front of
+    /// it, returning it if it was present.
+    pub fn remove(&mut self, path: &str) -> Result<Option<Value>> {
+        let path: Path = path.parse()?;
+        let Some((last, parent)) = path.segments().split_last() else {
+            return Err(Error::new(
+                ErrorKind::Custom,
+                "cannot remove the root value",
+            ));
+        };
+        let removed = match (self.root.node.get_mut(parent), last) {
+            (Some(Node::Dictionary(dict)), PathSegment::Key(key)) => {
+                let mut removed = None;
+                while let Some(idx) = dict.items.iter().position(|entry| entry.key == *key) {
+                    let entry = dict.remove(idx, |entry| &mut entry.prefix);
+                    removed = Some(entry.value.node.to_value());
+                }
+                removed
+            }
+            (Some(Node::Array(array)), PathSegment::Index(index)) if *index < array.items.len() => {
+                Some(
+                    array
+                        .remove(*index, |item| &mut item.prefix)
+                        .node
+                        .to_value(),
+                )
+            }
+            _ => None,
+        };
+        Ok(removed)
+    }
+
+    fn container_mut(&mut self, path: &[PathSegment]) -> Result<&mut Node> {
+        match self.root.node.get_mut(path) {
+            Some(node @ (Node::Array(_) | Node::Dictionary(_))) => Ok(node),
+            Some(_) => Err(Error::new(
+                ErrorKind::TypeMismatch,
+                "expected an array or a dictionary",
+            )
+            .with_path(path.iter().cloned().collect())),
+            None => Err(Error::new(ErrorKind::Custom, "no such value")
+                .with_path(path.iter().cloned().collect())),
+        }
+    }
+}
+
+fn mismatch(parent: &[PathSegment], segment: &PathSegment) -> Error {
+    let message = match segment {
+        PathSegment::Index(_) => "expected an array",
+        PathSegment::Key(_) => "expected a dictionary",
+    };
+    Error::new(ErrorKind::TypeMismatch, message).with_path(parent.iter().cloned().collect())
+}
+
+impl Node {
+    /// Moves the nodes directly inside this one to `pending`.
+    fn take_children(&mut self, pending: &mut Vec<Node>) {
+        match self {
+            Node::Scalar { .. } => {}
+            Node::Array(array) => pending.extend(array.items.drain(..).map(|item| item.node)),
+            Node::Dictionary(dict) => {
+                pending.extend(dict.items.drain(..).map(|entry| entry.value.node))
+            }
+        }
+    }
+
+    /// Returns the items of a container, or `None` for a scalar.
+    fn children(&self) -> Option<Children<'_>> {
+        match self {
+            Node::Scalar { .. } => None,
+            Node::Array(array) => Some(Children::Array(array.items.iter())),
+            Node::Dictionary(dict) => Some(Children::Dictionary(dict.items.iter())),
+        }
+    }
+
+    /// Builds a result for this node from the results for its items, without
+    /// recursing. `open` gives the result for a scalar, or the empty result
+    /// for a container, and `add` adds the result for an item to the result
+    /// for its container.
+    fn fold<'a, T, O, A>(&'a self, mut open: O, mut add: A) -> T
+    where
+        O: FnMut(&'a Node) -> T,
+        A: FnMut(&mut T, Option<&'a Entry>, &'a Item, T),
+    {
+        struct Frame<'a, T> {
+            children: Children<'a>,
+            result: T,
+            /// The item whose result is being built.
+            current: Option<(Option<&'a Entry>, &'a Item)>,
+        }
+
+        let mut stack: Vec<Frame<'a, T>> = Vec::new();
+        let mut node = self;
+        loop {
+            let result = open(node);
+            let mut done = match node.children() {
+                Some(children) => {
+                    stack.push(Frame {
+                        children,
+                        result,
+                        current: None,
+                    });
+                    None
+                }
+                None => Some(result),
+            };
+            // Adds finished results to their containers until one of them
+            // has another item to build.
+            loop {
+                let Some(frame) = stack.last_mut() else {
+                    return done.expect("the outermost node is finished");
+                };
+                if let (Some(result), Some((entry, item))) = (done.take(), frame.current.take()) {
+                    add(&mut frame.result, entry, item, result);
+                }
+                if let Some((entry, item)) = frame.children.next() {
+                    frame.current = Some((entry, item));
+                    node = &item.node;
+                    break;
+                }
+                done = stack.pop().map(|frame| frame.result);
+            }
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        self.fold(
+            |node| match node {
+                Node::Scalar { value, .. } => value.clone(),
+                Node::Array(array) => Value::Array(Vec::with_capacity(array.items.len())),
+                Node::Dictionary(dict) => {
+                    Value::Dictionary(IndexMap::with_capacity(dict.items.len()))
+                }
+            },
+            |container, entry, _, value| match (container, entry) {
+                (Value::Array(items), _) => items.push(value),
+                (Value::Dictionary(entries), Some(entry)) => {
+                    entries.insert(entry.key.clone(), value);
+                }
+                _ => unreachable!("items are added to the container they came from"),
+            },
+        )
+    }
+
+    fn get(&self, path: &[PathSegment]) -> Option<&Node> {
+        path.iter()
+            .try_fold(self, |node, segment| match (node, segment) {
+                (Node::Array(array), PathSegment::Index(index)) => {
+                    array.items.get(*index).map(|item| &item.node)
+                }
+                (Node::Dictionary(dict), PathSegment::Key(key)) => dict
+                    .items
+                    .iter()
+                    .rfind(|entry| entry.key == *key)
+                    .map(|entry| &entry.value.node),
+                _ => None,
+            })
+    }
+
+    fn get_mut(&mut self, path: &[PathSegment]) -> Option<&mut Node> {
+        path.iter()
+            .try_fold(self, |node, segment| match (node, segment) {
+                (Node::Array(array), PathSegment::Index(index)) => {
+                    array.items.get_mut(*index).map(|item| &mut item.node)
+                }
+                (Node::Dictionary(dict), PathSegment::Key(key)) => dict
+                    .items
+                    .iter_mut()
+                    .rfind(|entry| entry.key == *key)
+                    .map(|entry| &mut entry.value.node),
+                _ => None,
+            })
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> fmt::Result {
+        struct Frame<'a> {
+            children: Children<'a>,
+            trailing_comma: bool,
+            trailing: &'a str,
+            /// The suffix of the item being written.
+            suffix: Option<&'a str>,
+        }
+
+        // Containers are tracked on an explicit stack, as edits can put
+        // values of any depth into a document.
+        let mut stack: Vec<Frame<'_>> = Vec::new();
+        let mut next = Some(self);
+        loop {
+            if let Some(node) = next.take() {
+                let (open, trailing_comma, trailing) = match node {
+                    Node::Scalar {
+                        repr: Some(repr), ..
+                    } => {
+                        writer.write_str(repr)?;
+                        continue;
+                    }
+                    Node::Scalar { value, repr: None } => {
+                        value.write_with_options(writer, &SerializeOptions::default())?;
+                        continue;
+                    }
+                    Node::Array(array) => (&array.open, array.trailing_comma, &array.trailing),
+                    Node::Dictionary(dict) => (&dict.open, dict.trailing_comma, &dict.trailing),
+                };
+                writer.write_str(open)?;
+                stack.push(Frame {
+                    children: node.children().expect("containers have children"),
+                    trailing_comma,
+                    trailing,
+                    suffix: None,
+                });
+            }
+            let Some(frame) = stack.last_mut() else {
+                return Ok(());
+            };
+            if let Some(suffix) = frame.suffix.take() {
+                writer.write_str(suffix)?;
+                if frame.children.len() > 0 || frame.trailing_comma {
+                    writer.write_char(',')?;
+                }
+            }
+            match frame.children.next() {
+                Some((entry, item)) => {
+                    if let Some(entry) = entry {
+                        writer.write_str(&entry.prefix)?;
+                        match &entry.key_repr {
+                            Some(repr) => writer.write_str(repr)?,
+                            None => write_string(writer, &entry.key)?,
+                        }
+                        writer.write_str(&entry.separator)?;
+                    }
+                    writer.write_str(&item.prefix)?;
+                    frame.suffix = Some(&item.suffix);
+                    next = Some(&item.node);
+                }
+                None => {
+                    writer.write_str(frame.trailing)?;
+                    writer.write_char(']')?;
+                    stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// The items of a container node.
+enum Children<'a> {
+    Array(std::slice::Iter<'a, Item>),
+    Dictionary(std::slice::Iter<'a, Entry>),
+}
+
+impl<'a> Iterator for Children<'a> {
+    /// The entry, for dictionaries, and the item holding the value.
+    type Item = (Option<&'a Entry>, &'a Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Children::Array(items) => items.next().map(|item| (None, item)),
+            Children::Dictionary(entries) => {
+                entries.next().map(|entry| (Some(entry), &entry.value))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Children::Array(items) => items.size_hint(),
+            Children::Dictionary(entries) => entries.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for Children<'_> {}
+
+impl Clone for Node {
+    fn clone(&self) -> Self {
+        self.fold(
+            |node| match node {
+                Node::Scalar { value, repr } => Node::Scalar {
+                    value: value.clone(),
+                    repr: repr.clone(),
+                },
+                Node::Array(array) => Node::Array(array.clone_empty()),
+                Node::Dictionary(dict) => Node::Dictionary(dict.clone_empty()),
+            },
+            |container, entry, item, node| {
+                let item = Item {
+                    prefix: item.prefix.clone(),
+                    node,
+                    suffix: item.suffix.clone(),
+                };
+                match (container, entry) {
+                    (Node::Array(array), _) => array.items.push(item),
+                    (Node::Dictionary(dict), Some(entry)) => dict.items.push(Entry {
+                        prefix: entry.prefix.clone(),
+                        key: entry.key.clone(),
+                        key_repr: entry.key_repr.clone(),
+                        separator: entry.separator.clone(),
+                        value: item,
+                    }),
+                    _ => unreachable!("items are added to the container they came from"),
+                }
+            },
+        )
+    }
+}
+
+impl From<Value> for Node {
+    fn from(value: Value) -> Self {
+        /// A container whose items are being converted, together with the
+        /// key of the item being converted when it is a dictionary.
+        enum Partial {
+            Array(std::vec::IntoIter<Value>, Vec<Item>),
+            Dictionary(indexmap::map::IntoIter<String, Value>, Vec<Entry>, String),
+        }
+
+        // Edits accept values of any depth, so nested containers are kept on
+        // an explicit stack instead of being converted recursively.
+        let mut stack = Vec::new();
+        let mut next = value;
+        loop {
+            let mut done = match &mut next {
+                Value::Array(values) => {
+                    stack.push(Partial::Array(mem::take(values).into_iter(), Vec::new()));
+                    None
+                }
+                Value::Dictionary(entries) => {
+                    stack.push(Partial::Dictionary(
+                        mem::take(entries).into_iter(),
+                        Vec::new(),
+                        String::new(),
+                    ));
+                    None
+                }
+                value => Some(Node::Scalar {
+                    value: mem::take(value),
+                    repr: None,
+                }),
+            };
+            // Adds finished nodes to their containers until one of them has
+            // another item to convert.
+            loop {
+                let Some(partial) = stack.last_mut() else {
+                    return done.expect("the outermost node is finished");
+                };
+                match partial {
+                    Partial::Array(values, items) => {
+                        if let Some(node) = done.take() {
+                            items.push(Item {
+                                prefix: if items.is_empty() { "" } else { " " }.to_owned(),
+                                node,
+                                suffix: String::new(),
+                            });
+                        }
+                        if let Some(value) = values.next() {
+                            next = value;
+                            break;
+                        }
+                    }
+                    Partial::Dictionary(entries, items, key) => {
+                        if let Some(node) = done.take() {
+                            items.push(Entry {
+                                prefix: if items.is_empty() { "" } else { " " }.to_owned(),
+                                key: mem::take(key),
+                                key_repr: None,
+                                separator: " =>".to_owned(),
+                                value: Item {
+                                    prefix: " ".to_owned(),
+                                    node,
+                                    suffix: String::new(),
+                                },
+                            });
+                        }
+                        if let Some((entry_key, value)) = entries.next() {
+                            *key = entry_key;
+                            next = value;
+                            break;
+                        }
+                    }
+                }
+                done = match stack.pop() {
+                    Some(Partial::Array(_, items)) => Some(Node::Array(Container {
+                        open: "[".to_owned(),
+                        items,
+                        trailing_comma: false,
+                        trailing: String::new(),
+                    })),
+                    Some(Partial::Dictionary(_, items, _)) => Some(Node::Dictionary(Container {
+                        open: "%[".to_owned(),
+                        items,
+                        trailing_comma: false,
+                        trailing: String::new(),
+                    })),
+                    None => None,
+                };
+            }
+        }
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        // Edits can put values of any depth into a document, so nested nodes
+        // are flattened into a single list instead of dropped recursively.
+        let mut pending = Vec::new();
+        self.take_children(&mut pending);
+        while let Some(mut node) = pending.pop() {
+            node.take_children(&mut pending);
+        }
+    }
+}
+
+impl Item {
+    fn write<W: Write>(&self, writer: &mut W) -> fmt::Result {
+        writer.write_str(&self.prefix)?;
+        self.node.write(writer)?;
+        writer.write_str(&self.suffix)
+    }
+}
+
+impl<T> Container<T> {
+    /// Copies the container without its items.
+    fn clone_empty(&self) -> Self {
+        Container {
+            open: self.open.clone(),
+            items: Vec::with_capacity(self.items.len()),
+            trailing_comma: self.trailing_comma,
+            trailing: self.trailing.clone(),
+        }
+    }
+
+    /// Returns the prefix for an item added at the end, following the layout
+    /// of the last item. A comment on the line of the last item stays there.
+    fn take_append_prefix(&mut self, last_prefix: Option<&str>) -> String {
+        let Some(last_prefix) = last_prefix else {
+            return String::new();
+        };
+        let mut prefix = String::new();
+        if let Some(newline) = self.trailing.find('\n') {
+            let end = self.trailing[..newline].trim_end_matches('\r').len();
+            let same_line: String = self.trailing.drain(..end).collect();
+            if !same_line.trim().is_empty() {
+                prefix.push_str(&same_line);
+            }
+        }
+        prefix.push_str(indentation(last_prefix));
+        prefix
+    }
+
+    /// Removes the item at `idx`, handing its leading whitespace to the item
+    /// that takes its place.
+    fn remove<F>(&mut self, idx: usize, prefix: F) -> T
+    where
+        F: Fn(&mut T) -> &mut String,
+    {
+        let mut removed = self.items.remove(idx);
+        if let Some(next) = self.items.get_mut(idx) {
+            let removed_prefix = prefix(&mut removed);
+            let leading =
+                &removed_prefix[..removed_prefix.len() - removed_prefix.trim_start().len()];
+            let next_prefix = prefix(next);
+            *next_prefix = format!("{leading}{}", next_prefix.trim_start());
+        }
+        removed
+    }
+}
+
+impl Container<Item> {
+    fn insert_item(&mut self, idx: usize, node: Node) {
+        let prefix = match self.items.get(idx) {
+            Some(next) if idx == 0 => {
+                // The new first element takes over the layout of the old one.
+                let prefix = next.prefix.clone();
+                let leading = &prefix[..prefix.len() - prefix.trim_start().len()];
+                let indent = indentation(leading).to_owned();
+                self.items[idx].prefix = format!("{indent}{}", prefix.trim_start());
+                leading.to_owned()
+            }
+            Some(next) => indentation(&next.prefix).to_owned(),
+            None => {
+                let last = self.items.last().map(|item| item.prefix.clone());
+                self.take_append_prefix(last.as_deref())
+            }
+        };
+        self.items.insert(
+            idx,
+            Item {
+                prefix,
+                node,
+                suffix: String::new(),
+            },
+        );
+    }
+}
+
+impl Container<Entry> {
+    fn append_entry(&mut self, key: String, node: Node) {
+        let last = self.items.last();
+        let separator = last
+            .map_or(" =>", |entry| entry.separator.as_str())
+            .to_owned();
+        let value_prefix = last.map_or(" ", |entry| leading_whitespace(&entry.value.prefix));
+        let value_prefix = value_prefix.to_owned();
+        let last_prefix = last.map(|entry| entry.prefix.clone());
+        let prefix = self.take_append_prefix(last_prefix.as_deref());
+        self.items.push(Entry {
+            prefix,
+            key,
+            key_repr: None,
+            separator,
+            value: Item {
+                prefix: value_prefix,
+                node,
+                suffix: String::new(),
+            },
+        });
+    }
+}
+
+fn leading_whitespace(text: &str) -> &str {
+    &text[..text.len() - text.trim_start().len()]
+}
+
+/// Returns the line break and indentation that start the last line of
+/// `prefix`, or a single space if `prefix` does not contain a line break.
+fn indentation(prefix: &str) -> &str {
+    let Some(newline) = prefix.rfind('\n') else {
+        return " ";
+    };
+    let start = if prefix[..newline].ends_with('\r') {
+        newline - 1
+    } else {
+        newline
+    };
+    let line = &prefix[newline + 1..];
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    &prefix[start..newline + 1 + indent]
+}
+
+impl FromStr for Document {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let mut parser = DocumentParser {
+            input,
+            parser: Parser::new(input),
+        };
+        let mark = parser.parser.position();
+        parser.parser.peek_value_start()?;
+        let prefix = parser.text_since(mark);
+        let node = parser.parse_node()?;
+        let mark = parser.parser.position();
+        parser.parser.skip_ws()?;
+        if !parser.parser.is_eof() {
+            return Err(parser
+                .parser
+                .error(ErrorKind::Syntax, "unexpected trailing characters"));
+        }
+        Ok(Document {
+            root: Item {
+                prefix,
+                node,
+                suffix: parser.text_since(mark),
+            },
+        })
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.root.write(f)
+    }
+}
+
+impl fmt::Debug for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Document").field(&self.to_string()).finish()
+    }
+}
+
+impl From<Value> for Document {
+    fn from(value: Value) -> Self {
+        Document {
+            root: Item {
+                prefix: String::new(),
+                node: Node::from(value),
+                suffix: String::new(),
+            },
+        }
+    }
+}
+
+struct DocumentParser<'a> {
+    input: &'a str,
+    parser: Parser<'a>,
+}
+
+impl DocumentParser<'_> {
+    fn text_since(&self, mark: usize) -> String {
+        self.input[mark..self.parser.position()].to_owned()
+    }
+
+    /// Parses the value at the current position, which must be past any
+    /// whitespace and hints.
+    fn parse_node(&mut self) -> Result<Node> {
+        let start = self.parser.position();
+        match self.parser.peek_byte() {
+            Some(b'[') => {
+                self.parser.enter()?;
+                self.parser.expect_char('[')?;
+                let open = self.text_since(start);
+                let items = self.parse_container(open, |this, prefix| {
+                    let mark = this.parser.position();
+                    this.parser.peek_value_start()?;
+                    let hint = this.text_since(mark);
+                    Ok(Item {
+                        prefix: prefix + &hint,
+                        node: this.parse_node()?,
+                        suffix: String::new(),
+                    })
+                });
+                self.parser.leave();
+                items.map(Node::Array)
+            }
+            Some(b'%') => {
+                self.parser.enter()?;
+                self.parser.expect_char('%')?;
+                self.parser.skip_ws()?;
+                self.parser.expect_char('[')?;
+                let open = self.text_since(start);
+                let entries = self.parse_container(open, |this, prefix| {
+                    let mark = this.parser.position();
+                    let key = this.parser.parse_dict_key()?.into_owned();
+                    let key_repr = Some(this.text_since(mark));
+                    let mark = this.parser.position();
+                    this.parser.skip_ws()?;
+                    if !this.parser.consume_exact("=>") && !this.parser.consume_ascii(':') {
+                        return Err(this
+                            .parser
+                            .error(ErrorKind::Syntax, "expected '=>' after key"));
+                    }
+                    let separator = this.text_since(mark);
+                    let mark = this.parser.position();
+                    this.parser.peek_value_start()?;
+                    Ok(Entry {
+                        prefix,
+                        key,
+                        key_repr,
+                        separator,
+                        value: Item {
+                            prefix: this.text_since(mark),
+                            node: this.parse_node()?,
+                            suffix: String::new(),
+                        },
+                    })
+                });
+                self.parser.leave();
+                entries.map(Node::Dictionary)
+            }
+            _ => {
+                let value = self.parser.parse_value()?;
+                Ok(Node::Scalar {
+                    value,
+                    repr: Some(self.text_since(start)),
+                })
+            }
+        }
+    }
+
+    fn parse_container<T, F>(&mut self, open: String, mut parse_item: F) -> Result<Container<T>>
+    where
+        T: HasSuffix,
+        F: FnMut(&mut Self, String) -> Result<T>,
+    {
+        let mut container = Container {
+            open,
+            items: Vec::new(),
+            trailing_comma: false,
+            trailing: String::new(),
+        };
+        loop {
+            let mark = self.parser.position();
+            self.parser.skip_ws()?;
+            if self.parser.consume_ascii(']') {
+                container.trailing = self.input[mark..self.parser.position() - 1].to_owned();
+                container.trailing_comma = !container.items.is_empty();
+                return Ok(container);
+            }
+            let prefix = self.text_since(mark);
+            let mut item = parse_item(self, prefix)?;
+            let mark = self.parser.position();
+            self.parser.skip_ws()?;
+            if self.parser.consume_ascii(',') {
+                *item.suffix_mut() = self.input[mark..self.parser.position() - 1].to_owned();
+                container.items.push(item);
+            } else if self.parser.consume_ascii(']') {
+                container.trailing = self.input[mark..self.parser.position() - 1].to_owned();
+                container.items.push(item);
+                return Ok(container);
+            } else {
+                return Err(self.parser.error(ErrorKind::Syntax, "expected ',' or ']'"));
+            }
+        }
+    }
+}
+
+trait HasSuffix {
+    fn suffix_mut(&mut self) -> &mut String;
+}
+
+impl HasSuffix for Item {
+    fn suffix_mut(&mut self) -> &mut String {
+        &mut self.suffix
+    }
+}
+
+impl HasSuffix for Entry {
+    fn suffix_mut(&mut self) -> &mut String {
+        &mut self.value.suffix
+    }
+}
@@ -0,0 +1,263 @@
+use std::borrow::Cow;
+use std::{fmt, io, str};
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::storage;
+
+/// Text encodings understood by the byte-level entry points.
+///
+/// The set of variants depends on the enabled features, so matches on this
+/// enum need a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Shift_JIS as used by Windows (code page 932).
+    #[cfg(feature = "sjis")]
+    ShiftJis,
+}
+
+impl Encoding {
+    /// Detects a byte order mark at the start of `input`, returning the
+    /// encoding it announces together with the length of the mark.
+    pub fn detect_bom(input: &[u8]) -> Option<(Encoding, usize)> {
+        match input {
+            [0xEF, 0xBB, 0xBF, ..] => Some((Encoding::Utf8, 3)),
+            [0xFF, 0xFE, ..] => Some((Encoding::Utf16Le, 2)),
+            [0xFE, 0xFF, ..] => Some((Encoding::Utf16Be, 2)),
+            _ => None,
+        }
+    }
+
+    fn byte_order_mark(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+            Encoding::Utf16Le => &[0xFF, 0xFE],
+            Encoding::Utf16Be => &[0xFE, 0xFF],
+            #[cfg(feature = "sjis")]
+            Encoding::ShiftJis => &[],
+        }
+    }
+}
+
+/// Adapts an [`io::Write`] into a [`fmt::Write`] that encodes text as it is
+/// written.
+pub(crate) struct EncodedWriter<W> {
+    writer: W,
+    encoding: Encoding,
+    /// Applied to every UTF-16 code unit before it is written.
+    transform: fn(u16) -> u16,
+    buffer: Vec<u8>,
+    /// Number of characters written so far.
+    written: usize,
+    error: Option<Error>,
+}
+
+impl<W: io::Write> EncodedWriter<W> {
+    pub(crate) fn new(writer: W, encoding: Encoding) -> Self {
+        Self {
+            writer,
+            encoding,
+            transform: |unit| unit,
+            buffer: Vec::new(),
+            written: 0,
+            error: None,
+        }
+    }
+
+    /// Creates a writer producing UTF-16LE text with every code unit passed
+    /// through `transform`.
+    pub(crate) fn scrambled(writer: W, transform: fn(u16) -> u16) -> Self {
+        Self {
+            transform,
+            ..Self::new(writer, Encoding::Utf16Le)
+        }
+    }
+
+    pub(crate) fn write_bom(&mut self) -> Result<()> {
+        self.writer
+            .write_all(self.encoding.byte_order_mark())
+            .map_err(Error::io)
+    }
+
+    /// Converts a formatting failure into the error that caused it.
+    pub(crate) fn take_error(&mut self) -> Error {
+        self.error
+            .take()
+            .unwrap_or_else(|| Error::new(ErrorKind::Custom, "failed to format TJS2 text"))
+    }
+
+    /// Returns the error that made writing fail, if there was one, or `err`.
+    pub(crate) fn take_error_or(&mut self, err: Error) -> Error {
+        self.error.take().unwrap_or(err)
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn encode(&mut self, text: &str) -> Result<()> {
+        let bytes = match self.encoding {
+            Encoding::Utf8 => text.as_bytes(),
+            Encoding::Utf16Le => {
+                self.buffer.clear();
+                self.buffer.extend(
+                    text.encode_utf16()
+                        .map(self.transform)
+                        .flat_map(u16::to_le_bytes),
+                );
+                &self.buffer
+            }
+            Encoding::Utf16Be => {
+                self.buffer.clear();
+                self.buffer.extend(
+                    text.encode_utf16()
+                        .map(self.transform)
+                        .flat_map(u16::to_be_bytes),
+                );
+                &self.buffer
+            }
+            #[cfg(feature = "sjis")]
+            Encoding::ShiftJis => {
+                encode_shift_jis(text, self.written, &mut self.buffer)?;
+                &self.buffer
+            }
+        };
+        self.writer.write_all(bytes).map_err(Error::io)?;
+        self.written += text.chars().count();
+        Ok(())
+    }
+}
+
+impl<W: io::Write> fmt::Write for EncodedWriter<W> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.encode(text).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+/// Encodes `text` into `output`, where `offset` is the number of characters
+/// written before `text`.
+#[cfg(feature = "sjis")]
+fn encode_shift_jis(text: &str, offset: usize, output: &mut Vec<u8>) -> Result<()> {
+    use encoding_rs::{EncoderResult, SHIFT_JIS};
+
+    let mut encoder = SHIFT_JIS.new_encoder();
+    let mut rest = text;
+    output.clear();
+    loop {
+        if let Some(needed) = encoder.max_buffer_length_from_utf8_without_replacement(rest.len()) {
+            output.reserve(needed);
+        }
+        let (result, read) =
+            encoder.encode_from_utf8_to_vec_without_replacement(rest, output, true);
+        match result {
+            EncoderResult::InputEmpty => return Ok(()),
+            EncoderResult::OutputFull => rest = &rest[read..],
+            EncoderResult::Unmappable(ch) => {
+                let end = text.len() - rest.len() + read - ch.len_utf8();
+                let position = offset + text[..end].chars().count();
+                return Err(Error::new(
+                    ErrorKind::Encoding,
+                    format!(
+                        "character {ch:?} (U+{:04X}) at character {position} cannot be encoded as Shift_JIS",
+                        ch as u32
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Decodes `input` into text. A Kirikiri storage header or a byte order mark
+/// takes precedence over `fallback`, which is only used for plain input
+/// without a byte order mark. Compressed text may inflate to at most
+/// `max_len` bytes.
+pub(crate) fn decode(
+    input: &[u8],
+    fallback: Encoding,
+    max_len: Option<usize>,
+) -> Result<Cow<'_, str>> {
+    if let Some(text) = storage::decode(input, max_len) {
+        return text.map(Cow::Owned);
+    }
+    let (encoding, body, offset) = match Encoding::detect_bom(input) {
+        Some((encoding, len)) => (encoding, &input[len..], len),
+        None => (fallback, input, 0),
+    };
+    match encoding {
+        Encoding::Utf8 => str::from_utf8(body).map(Cow::Borrowed).map_err(Error::utf8),
+        Encoding::Utf16Le => decode_utf16(body, offset, u16::from_le_bytes).map(Cow::Owned),
+        Encoding::Utf16Be => decode_utf16(body, offset, u16::from_be_bytes).map(Cow::Owned),
+        #[cfg(feature = "sjis")]
+        Encoding::ShiftJis => decode_shift_jis(body, offset).map(Cow::Owned),
+    }
+}
+
+#[cfg(feature = "sjis")]
+fn decode_shift_jis(input: &[u8], offset: usize) -> Result<String> {
+    use encoding_rs::{DecoderResult, SHIFT_JIS};
+
+    let mut decoder = SHIFT_JIS.new_decoder_without_bom_handling();
+    let mut output = String::new();
+    let mut consumed = 0;
+    loop {
+        let rest = &input[consumed..];
+        if let Some(needed) = decoder.max_utf8_buffer_length_without_replacement(rest.len()) {
+            output.reserve(needed);
+        }
+        let (result, read) = decoder.decode_to_string_without_replacement(rest, &mut output, true);
+        consumed += read;
+        match result {
+            DecoderResult::InputEmpty => return Ok(output),
+            DecoderResult::OutputFull => {}
+            DecoderResult::Malformed(malformed, extra) => {
+                let position = offset + consumed - malformed as usize - extra as usize;
+                return Err(Error::with_position(
+                    ErrorKind::Encoding,
+                    "input is not valid Shift_JIS",
+                    position,
+                ));
+            }
+        }
+    }
+}
+
+/// Decodes UTF-16 `input`, where `offset` is the position of `input` within
+/// the whole document and `unit` reads one code unit.
+pub(crate) fn decode_utf16<F>(input: &[u8], offset: usize, unit: F) -> Result<String>
+where
+    F: Fn([u8; 2]) -> u16,
+{
+    if !input.len().is_multiple_of(2) {
+        return Err(Error::with_position(
+            ErrorKind::Encoding,
+            "input is not valid UTF-16: odd number of bytes",
+            offset + input.len() - 1,
+        ));
+    }
+    let units = input.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    let mut output = String::with_capacity(input.len() / 2);
+    let mut position = offset;
+    for ch in char::decode_utf16(units) {
+        match ch {
+            Ok(ch) => {
+                position += ch.len_utf16() * 2;
+                output.push(ch);
+            }
+            Err(_) => {
+                return Err(Error::with_position(
+                    ErrorKind::Encoding,
+                    "input is not valid UTF-16: unpaired surrogate",
+                    position,
+                ));
+            }
+        }
+    }
+    Ok(output)
+}
@@ -1,55 +1,296 @@
-use std::fmt;
+use std::sync::Arc;
+use std::{fmt, io, str};
+
+use crate::path::{Path, PathSegment};
 
 /// A unified error type for parsing and serializing TJS structures.
 #[derive(Debug, Clone)]
 pub struct Error {
+    pub(crate) kind: ErrorKind,
     pub(crate) message: String,
     pub(crate) position: Option<usize>,
+    pub(crate) line_column: Option<(usize, usize)>,
+    pub(crate) snippet: Option<Box<str>>,
+    pub(crate) path: Path,
+    pub(crate) source: Option<Arc<io::Error>>,
+}
+
+/// Broad classification of an [`Error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The input is not well-formed TJS2 text.
+    Syntax,
+    /// The input ended before a complete value was read.
+    Eof,
+    /// A value does not have the type or shape the target expects.
+    TypeMismatch,
+    /// A number does not fit in the range of its type.
+    Overflow,
+    /// A string literal contains a malformed escape sequence.
+    InvalidEscape,
+    /// The input is not valid in its text encoding, or text cannot be encoded.
+    Encoding,
+    /// A dictionary contains the same key more than once.
+    DuplicateKey,
+    /// Arrays and dictionaries are nested deeper than the configured limit.
+    DepthLimitExceeded,
+    /// The input is larger than the configured limit.
+    InputLimitExceeded,
+    /// A string is longer than the configured limit.
+    StringLimitExceeded,
+    /// An octet literal is longer than the configured limit.
+    OctetLimitExceeded,
+    /// An array or dictionary has more elements than the configured limit.
+    ContainerLimitExceeded,
+    /// The input contains more values than the configured limit.
+    NodeLimitExceeded,
+    /// Reading the input or writing the output failed.
+    Io,
+    /// A message produced by a `Serialize` or `Deserialize` implementation.
+    Custom,
 }
 
 /// Convenient result alias used throughout the crate.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Number of characters shown on either side of the error in a snippet.
+const SNIPPET_CONTEXT: usize = 40;
+
 impl Error {
-    pub(crate) fn new(message: impl Into<String>) -> Self {
+    pub(crate) fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
         Self {
+            kind,
             message: message.into(),
             position: None,
+            line_column: None,
+            snippet: None,
+            path: Path::default(),
+            source: None,
         }
     }
 
-    pub(crate) fn with_position(message: impl Into<String>, position: usize) -> Self {
+    pub(crate) fn with_position(
+        kind: ErrorKind,
+        message: impl Into<String>,
+        position: usize,
+    ) -> Self {
         Self {
-            message: message.into(),
             position: Some(position),
+            ..Self::new(kind, message)
+        }
+    }
+
+    pub(crate) fn io(err: io::Error) -> Self {
+        let message = format!("I/O error: {err}");
+        Self {
+            source: Some(Arc::new(err)),
+            ..Self::new(ErrorKind::Io, message)
+        }
+    }
+
+    pub(crate) fn utf8(err: str::Utf8Error) -> Self {
+        Self::new(
+            ErrorKind::Encoding,
+            format!("input is not valid UTF-8: {err}"),
+        )
+    }
+
+    /// Records `position` as the location of this error unless a more precise
+    /// one is already known.
+    pub(crate) fn or_position(mut self, position: usize) -> Self {
+        self.position.get_or_insert(position);
+        self
+    }
+
+    /// Records that this error happened inside the child `segment`.
+    pub(crate) fn prepend_path(mut self, segment: PathSegment) -> Self {
+        self.path.push_front(segment);
+        self
+    }
+
+    /// Records the path of the value this error refers to.
+    pub(crate) fn with_path(mut self, path: Path) -> Self {
+        self.path = path;
+        self
+    }
+
+    /// Resolves the byte offset of this error against the text it refers to,
+    /// filling in the line, column and snippet.
+    pub(crate) fn with_source(mut self, source: &str) -> Self {
+        if let Some(position) = self.position
+            && self.line_column.is_none()
+            && let Some(before) = source.get(..position)
+        {
+            let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+            let (line, column) = line_column(before);
+            let line_end = source[position..]
+                .find('\n')
+                .map_or(source.len(), |idx| position + idx);
+            self.line_column = Some((line, column));
+            self.snippet = Some(
+                render_snippet(
+                    &source[line_start..position],
+                    source[position..line_end].trim_end_matches('\r'),
+                    line,
+                )
+                .into_boxed_str(),
+            );
         }
+        self
+    }
+
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns `true` if the input ended before a complete value was read.
+    pub fn is_eof(&self) -> bool {
+        self.kind == ErrorKind::Eof
+    }
+
+    /// Returns `true` if the input is not well-formed TJS2 text.
+    ///
+    /// Malformed escape sequences and out-of-range numbers count as syntax
+    /// errors here, even though [`Error::kind`] reports them separately.
+    pub fn is_syntax(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::Syntax | ErrorKind::Overflow | ErrorKind::InvalidEscape
+        )
+    }
+
+    /// Returns `true` if reading the input or writing the output failed.
+    pub fn is_io(&self) -> bool {
+        self.kind == ErrorKind::Io
+    }
+
+    /// Returns the path of the value that failed to deserialize (when available).
+    pub fn path(&self) -> Option<&Path> {
+        (!self.path.is_empty()).then_some(&self.path)
     }
 
     /// Returns the byte offset within the source (when available).
     pub fn position(&self) -> Option<usize> {
         self.position
     }
+
+    /// Returns the 1-based line of the error within the source text (when available).
+    pub fn line(&self) -> Option<usize> {
+        self.line_column.map(|(line, _)| line)
+    }
+
+    /// Returns the 1-based column of the error, counted in characters (when available).
+    pub fn column(&self) -> Option<usize> {
+        self.line_column.map(|(_, column)| column)
+    }
+
+    /// Returns the offending source line with a caret under the failing token
+    /// (when available).
+    ///
+    /// The alternate form of `Display` (`{:#}`) appends this snippet to the message.
+    pub fn snippet(&self) -> Option<&str> {
+        self.snippet.as_deref()
+    }
+}
+
+/// Returns the 1-based line and column (in characters) just past `before`.
+pub(crate) fn line_column(before: &str) -> (usize, usize) {
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    let line = before.matches('\n').count() + 1;
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Renders the line around an error as
+///
+/// ```text
+///  3 |     "age" => 4x3,
+///    |               ^
+/// ```
+///
+/// keeping at most [`SNIPPET_CONTEXT`] characters on either side of the caret.
+fn render_snippet(before: &str, after: &str, line: usize) -> String {
+    let skipped = before.chars().count().saturating_sub(SNIPPET_CONTEXT);
+    let mut before_shown: String = before.chars().skip(skipped).collect();
+    if skipped > 0 {
+        before_shown.insert_str(0, "...");
+    }
+    let mut after_shown: String = after.chars().take(SNIPPET_CONTEXT).collect();
+    if after_shown.len() < after.len() {
+        after_shown.push_str("...");
+    }
+
+    // Keep tabs in the padding so the caret lines up with the source line.
+    let padding: String = before_shown
+        .chars()
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+    let gutter = line.to_string();
+    let blank = " ".repeat(gutter.len());
+    format!("{gutter} | {before_shown}{after_shown}\n{blank} | {padding}^")
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.position {
-            Some(pos) => write!(f, "{} at byte {}", self.message, pos),
-            None => f.write_str(&self.message),
+        f.write_str(&self.message)?;
+        let separator = if self.path.is_empty() {
+            " at"
+        } else {
+            write!(f, " at {}", self.path)?;
+            ","
+        };
+        match (self.line_column, self.position) {
+            (Some((line, column)), _) => write!(f, "{separator} line {line} column {column}")?,
+            (None, Some(pos)) => write!(f, "{separator} byte {pos}")?,
+            (None, None) => {}
         }
+        if f.alternate()
+            && let Some(snippet) = &self.snippet
+        {
+            write!(f, "\n{snippet}")?;
+        }
+        Ok(())
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|err| err as &(dyn std::error::Error + 'static))
+    }
+}
 
 impl serde::ser::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Self {
-        Error::new(msg.to_string())
+        Error::new(ErrorKind::Custom, msg.to_string())
     }
 }
 
 impl serde::de::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Self {
-        Error::new(msg.to_string())
+        Error::new(ErrorKind::Custom, msg.to_string())
+    }
+
+    fn invalid_type(unexp: serde::de::Unexpected<'_>, exp: &dyn serde::de::Expected) -> Self {
+        Error::new(
+            ErrorKind::TypeMismatch,
+            format!("invalid type: {unexp}, expected {exp}"),
+        )
+    }
+
+    fn invalid_value(unexp: serde::de::Unexpected<'_>, exp: &dyn serde::de::Expected) -> Self {
+        Error::new(
+            ErrorKind::TypeMismatch,
+            format!("invalid value: {unexp}, expected {exp}"),
+        )
+    }
+
+    fn invalid_length(len: usize, exp: &dyn serde::de::Expected) -> Self {
+        Error::new(
+            ErrorKind::TypeMismatch,
+            format!("invalid length {len}, expected {exp}"),
+        )
     }
 }
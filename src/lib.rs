@@ -39,18 +39,34 @@
 //! # }
 //! ```
 mod de;
+pub mod edit;
+mod encoding;
 mod error;
 mod parser;
+mod path;
 mod ser;
+mod spanned;
+mod storage;
 mod value;
 
-pub use crate::de::{from_slice, from_str, from_value, parse_value};
-pub use crate::error::{Error, Result};
+pub use crate::de::{
+    Deserializer, from_reader, from_reader_with_options, from_slice, from_slice_with_encoding,
+    from_slice_with_options, from_str, from_str_with_options, from_value, parse_value,
+    parse_value_from_reader, parse_value_from_reader_with_options, parse_value_from_slice,
+    parse_value_from_slice_with_encoding, parse_value_from_slice_with_options,
+    parse_value_recovering, parse_value_with_duplicates, parse_value_with_options,
+};
+pub use crate::encoding::Encoding;
+pub use crate::error::{Error, ErrorKind, Result};
+pub use crate::parser::{DuplicateKeys, ParseOptions};
+pub use crate::path::{Path, PathSegment};
 pub use crate::ser::{
-    to_string, to_string_pretty, to_string_with_options, to_value, to_vec, to_vec_pretty,
-    to_vec_with_options, to_writer, to_writer_pretty, to_writer_with_options,
+    Serializer, to_string, to_string_pretty, to_string_with_options, to_value, to_vec,
+    to_vec_pretty, to_vec_with_options, to_writer, to_writer_pretty, to_writer_with_options,
 };
-pub use crate::value::{SerializeOptions, Value};
+pub use crate::spanned::Spanned;
+pub use crate::storage::Storage;
+pub use crate::value::{FloatStyle, KeyOrder, Newline, OctetStyle, Quote, SerializeOptions, Value};
 
 #[cfg(test)]
 mod tests {
@@ -107,7 +123,7 @@ mod tests {
         ]"#;
 
         let value = parse_value(input).expect("failed to parse");
-        match value {
+        match &value {
             Value::Array(items) => {
                 assert_eq!(items.len(), 5);
                 assert_eq!(items[0], Value::Integer(1));
@@ -133,12 +149,1360 @@ mod tests {
         assert_eq!(data, restored);
     }
 
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect { w: i64, h: i64 },
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Scene {
+        title: Option<String>,
+        shapes: Vec<Shape>,
+        origin: (i32, i32),
+    }
+
+    #[test]
+    fn streaming_deserializer_matches_value_path() {
+        let input = r#"(const) %[
+            // comment before the first entry
+            "title" => void,
+            shapes: (const) [
+                "Empty",
+                (const) %["Circle" => 1.5],
+                %["Rect" => %["w" => 2, "h" => 3]],
+            ],
+            "origin" => [0x10, -4]
+        ]"#;
+
+        let expected = Scene {
+            title: None,
+            shapes: vec![Shape::Empty, Shape::Circle(1.5), Shape::Rect { w: 2, h: 3 }],
+            origin: (16, -4),
+        };
+        let streamed: Scene = from_str(input).expect("streaming deserialize");
+        assert_eq!(streamed, expected);
+        let via_value: Scene =
+            crate::from_value(parse_value(input).expect("parse")).expect("value deserialize");
+        assert_eq!(via_value, expected);
+
+        // Unknown fields are skipped without being built, but still checked.
+        let input = r#"%[
+            "extra" => %[a => [1, 0x1p4, "x\u0041" + 'y', <% 0a ff %>], b: void,],
+            "title" => "t", "shapes" => [], "origin" => [1, 2]
+        ]"#;
+        let scene: Scene = from_str(input).expect("skip unknown field");
+        assert_eq!(scene.title.as_deref(), Some("t"));
+        let err = from_str::<Scene>(r#"%["extra" => [1 2], "shapes" => []]"#).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::Syntax);
+        let options = crate::ParseOptions {
+            max_octet_len: Some(1),
+            ..crate::ParseOptions::default()
+        };
+        let err = crate::from_str_with_options::<Scene>(input, &options).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::OctetLimitExceeded);
+        let options = crate::ParseOptions {
+            max_string_len: Some(6),
+            ..crate::ParseOptions::default()
+        };
+        // The limit applies to the decoded text of the six-byte string.
+        let input =
+            r#"%["extra" => "\u0041\u0041\u0041" '\x41\x41\x41', "shapes" => [], origin: [1, 2]]"#;
+        crate::from_str_with_options::<Scene>(input, &options).expect("string within limit");
+        let input = input.replace("',", "A',");
+        let err = crate::from_str_with_options::<Scene>(&input, &options).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::StringLimitExceeded);
+
+        let mut deserializer = crate::Deserializer::new("[1, 2] [3]");
+        let first: Vec<i32> = serde::Deserialize::deserialize(&mut deserializer).expect("first");
+        assert_eq!(first, vec![1, 2]);
+        assert!(deserializer.end().is_err());
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Borrowed<'a> {
+        name: &'a str,
+        #[serde(borrow)]
+        note: std::borrow::Cow<'a, str>,
+    }
+
+    #[test]
+    fn borrows_strings_without_escapes() {
+        let input = r#"%["name" => "plain", note => 'tab\there']"#;
+        let borrowed: Borrowed<'_> = from_str(input).expect("borrowed deserialize");
+        assert_eq!(borrowed.name, "plain");
+        assert_eq!(borrowed.note, "tab\there");
+        assert!(matches!(borrowed.note, std::borrow::Cow::Owned(_)));
+
+        let input = r#"%["name" => "a", "note" => "b"]"#;
+        let borrowed: Borrowed<'_> = crate::from_slice(input.as_bytes()).expect("from_slice");
+        assert!(matches!(borrowed.note, std::borrow::Cow::Borrowed("b")));
+
+        let escaped = r#"%["name" => "needs\nescape", "note" => ""]"#;
+        assert!(from_str::<Borrowed<'_>>(escaped).is_err());
+    }
+
+    #[test]
+    fn reader_helpers_report_io_and_utf8_separately() {
+        let data = SampleStruct {
+            name: "reader".to_string(),
+            score: -1,
+            flags: vec![true],
+        };
+        let bytes = crate::to_vec(&data).expect("to_vec");
+        let restored: SampleStruct = crate::from_reader(bytes.as_slice()).expect("from_reader");
+        assert_eq!(restored, data);
+        let value = crate::parse_value_from_reader(bytes.as_slice()).expect("parse reader");
+        assert_eq!(
+            value,
+            parse_value(&crate::to_string(&data).unwrap()).unwrap()
+        );
+
+        struct FailingReader;
+        impl std::io::Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disk on fire"))
+            }
+        }
+        let err = crate::from_reader::<_, SampleStruct>(FailingReader).unwrap_err();
+        assert!(err.to_string().starts_with("I/O error"));
+        let err = crate::from_reader::<_, SampleStruct>(&b"\"\xff\""[..]).unwrap_err();
+        assert!(err.to_string().contains("UTF-8"));
+    }
+
+    fn utf16(text: &str, bom: bool, unit: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if bom {
+            bytes.extend(unit(0xFEFF));
+        }
+        bytes.extend(text.encode_utf16().flat_map(unit));
+        bytes
+    }
+
+    #[test]
+    fn decodes_byte_order_marks_and_explicit_encodings() {
+        let data = SampleStruct {
+            name: "セーブ".to_string(),
+            score: 3,
+            flags: vec![true],
+        };
+        let text = crate::to_string(&data).expect("serialize");
+
+        let le = utf16(&text, true, u16::to_le_bytes);
+        let restored: SampleStruct = crate::from_slice(&le).expect("utf-16le");
+        assert_eq!(restored, data);
+        let be = utf16(&text, true, u16::to_be_bytes);
+        let restored: SampleStruct = crate::from_reader(be.as_slice()).expect("utf-16be");
+        assert_eq!(restored, data);
+        let mut utf8 = vec![0xEF, 0xBB, 0xBF];
+        utf8.extend(text.as_bytes());
+        assert_eq!(
+            crate::parse_value_from_slice(&utf8).expect("utf-8 bom"),
+            parse_value(&text).unwrap()
+        );
+
+        let bare = utf16(&text, false, u16::to_le_bytes);
+        assert!(crate::from_slice::<SampleStruct>(&bare).is_err());
+        let restored: SampleStruct =
+            crate::from_slice_with_encoding(&bare, crate::Encoding::Utf16Le).expect("explicit");
+        assert_eq!(restored, data);
+
+        let spanned: crate::Spanned<Vec<i64>> =
+            crate::from_slice(&utf16(" [1, 2]", true, u16::to_le_bytes)).expect("spanned");
+        assert_eq!(spanned.span(), 1..7);
+
+        let mut truncated = le.clone();
+        truncated.pop();
+        assert!(crate::parse_value_from_slice(&truncated).is_err());
+    }
+
+    #[test]
+    fn writes_requested_output_encoding() {
+        let data = SampleStruct {
+            name: "吉里吉里".to_string(),
+            score: 2,
+            flags: vec![],
+        };
+        let text = crate::to_string(&data).expect("serialize");
+
+        let options = SerializeOptions {
+            encoding: crate::Encoding::Utf16Le,
+            write_bom: true,
+            ..SerializeOptions::default()
+        };
+        let bytes = crate::to_vec_with_options(&data, &options).expect("utf-16le");
+        assert_eq!(bytes, utf16(&text, true, u16::to_le_bytes));
+        let restored: SampleStruct = crate::from_slice(&bytes).expect("roundtrip");
+        assert_eq!(restored, data);
+
+        let options = SerializeOptions {
+            encoding: crate::Encoding::Utf16Be,
+            ..SerializeOptions::default()
+        };
+        let mut buffer = Vec::new();
+        crate::to_writer_with_options(&mut buffer, &data, &options).expect("utf-16be");
+        assert_eq!(buffer, utf16(&text, false, u16::to_be_bytes));
+
+        let options = SerializeOptions {
+            write_bom: true,
+            ..SerializeOptions::default()
+        };
+        let bytes = crate::to_vec_with_options(&data, &options).expect("utf-8 bom");
+        assert_eq!(&bytes[..3], &[0xEF, 0xBB, 0xBF]);
+        assert_eq!(&bytes[3..], text.as_bytes());
+    }
+
+    #[cfg(feature = "sjis")]
+    #[test]
+    fn shift_jis_roundtrip_and_errors() {
+        let data = SampleStruct {
+            name: "セーブデータ".to_string(),
+            score: 9,
+            flags: vec![true],
+        };
+        let options = SerializeOptions {
+            encoding: crate::Encoding::ShiftJis,
+            write_bom: true,
+            ..SerializeOptions::default()
+        };
+        let bytes = crate::to_vec_with_options(&data, &options).expect("encode");
+        assert!(!bytes.starts_with(&[0xEF, 0xBB, 0xBF]));
+        assert!(bytes.windows(2).any(|pair| pair == [0x83, 0x5A]));
+        let restored: SampleStruct =
+            crate::from_slice_with_encoding(&bytes, crate::Encoding::ShiftJis).expect("decode");
+        assert_eq!(restored, data);
+
+        let err = crate::parse_value_from_slice_with_encoding(
+            b"[1, \"\x82\"]",
+            crate::Encoding::ShiftJis,
+        )
+        .unwrap_err();
+        assert_eq!(err.position(), Some(5));
+
+        let err = crate::to_vec_with_options(&"ab😀", &options).unwrap_err();
+        assert!(err.to_string().contains("at character 3"), "{err}");
+    }
+
+    #[test]
+    fn kirikiri_storage_modes_roundtrip() {
+        let data = SampleStruct {
+            name: "暗号化 save".to_string(),
+            score: 12,
+            flags: vec![false, true],
+        };
+        let text = crate::to_string(&data).expect("serialize");
+        for (storage, mode) in [
+            (crate::Storage::XorScrambled, 0),
+            (crate::Storage::BitSwapped, 1),
+            (crate::Storage::Compressed, 2),
+        ] {
+            let options = SerializeOptions {
+                storage,
+                ..SerializeOptions::default()
+            };
+            let bytes = crate::to_vec_with_options(&data, &options).expect("encode");
+            assert_eq!(&bytes[..5], &[0xFE, 0xFE, mode, 0xFF, 0xFE]);
+            assert_ne!(
+                &bytes[5..],
+                utf16(&text, false, u16::to_le_bytes).as_slice()
+            );
+            let restored: SampleStruct = crate::from_slice(&bytes).expect("decode");
+            assert_eq!(restored, data);
+        }
+
+        // "[1]" scrambled with mode 0 by hand.
+        let scrambled = [
+            0xFE, 0xFE, 0x00, 0xFF, 0xFE, 0x5A, 0x5A, 0x30, 0x30, 0x5C, 0x5C,
+        ];
+        assert_eq!(
+            crate::parse_value_from_slice(&scrambled).expect("mode 0"),
+            Value::Array(vec![Value::Integer(1)])
+        );
+        assert!(crate::parse_value_from_slice(&[0xFE, 0xFE, 0x03, 0xFF, 0xFE]).is_err());
+        assert!(crate::parse_value_from_slice(&[0xFE, 0xFE, 0x02, 0xFF, 0xFE, 1, 0]).is_err());
+
+        // Mode 2 headers whose lengths disagree with the data are rejected
+        // without inflating more than announced.
+        let compressed_file = |data: &[u8], compressed: usize, uncompressed: u64| {
+            use std::io::Write;
+
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(data).unwrap();
+            let body = encoder.finish().unwrap();
+            let mut bytes = vec![0xFE, 0xFE, 0x02, 0xFF, 0xFE];
+            bytes.extend((compressed.min(body.len()) as u64).to_le_bytes());
+            bytes.extend(uncompressed.to_le_bytes());
+            bytes.extend(body);
+            bytes
+        };
+        let bomb = vec![b' '; 1 << 20];
+        let err =
+            crate::parse_value_from_slice(&compressed_file(&bomb, usize::MAX, 4)).unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{err}");
+        let err = crate::parse_value_from_slice(&compressed_file(&bomb, usize::MAX, u64::MAX - 1))
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid uncompressed"), "{err}");
+        let err =
+            crate::parse_value_from_slice(&compressed_file(b"[\x001\x00]\x00", 4, 6)).unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{err}");
+    }
+
+    #[test]
+    fn errors_report_line_column_and_snippet() {
+        let input = "%[\n  \"名前\" => \"a\",\n\t\"age\" => 4x3\n]";
+        let err = parse_value(input).unwrap_err();
+        assert_eq!(err.position(), Some(input.find('x').unwrap()));
+        assert_eq!((err.line(), err.column()), Some((3, 12)).unzip());
+        assert_eq!(
+            err.snippet(),
+            Some("3 | \t\"age\" => 4x3\n  | \t          ^")
+        );
+        assert_eq!(err.to_string(), "expected ',' or ']' at line 3 column 12");
+        assert!(format!("{err:#}").ends_with("\t          ^"));
+
+        let err = from_str::<SampleStruct>("%[\"name\" => \"名前\", \"score\" => ]").unwrap_err();
+        assert_eq!((err.line(), err.column()), (Some(1), Some(30)));
+
+        let long_line = format!("[{}oops]", "1, ".repeat(100));
+        let snippet = parse_value(&long_line)
+            .unwrap_err()
+            .snippet()
+            .unwrap()
+            .to_string();
+        assert!(snippet.starts_with("1 | ...") && snippet.lines().next().unwrap().len() < 100);
+    }
+
+    #[test]
+    fn errors_are_classified_by_kind() {
+        use crate::ErrorKind;
+
+        let kind = |input: &str| parse_value(input).unwrap_err().kind();
+        assert_eq!(kind("[1, ?]"), ErrorKind::Syntax);
+        assert_eq!(kind("[1, 2"), ErrorKind::Eof);
+        assert_eq!(kind("\"open"), ErrorKind::Eof);
+        assert_eq!(kind("0x1ffffffffffffffff"), ErrorKind::Overflow);
+        assert_eq!(kind("\"\\xzz\""), ErrorKind::InvalidEscape);
+        assert!(parse_value("%[\"a\" => ").unwrap_err().is_eof());
+        assert!(parse_value("[1 2]").unwrap_err().is_syntax());
+
+        let err =
+            from_str::<SampleStruct>(r#"%["name" => 1, "score" => 2, "flags" => []]"#).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        let err = crate::from_value::<u8>(Value::Integer(300)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        let err = from_str::<SampleStruct>(r#"%["name" => "x"]"#).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Custom);
+        let err = crate::from_slice::<SampleStruct>(b"\"\xff\"").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Encoding);
+
+        struct FailingReader;
+        impl std::io::Read for FailingReader {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "gone"))
+            }
+        }
+        let err = crate::parse_value_from_reader(FailingReader).unwrap_err();
+        assert!(err.is_io());
+        let source = std::error::Error::source(&err).expect("io source");
+        let io = source.downcast_ref::<std::io::Error>().expect("io::Error");
+        assert_eq!(io.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn errors_report_field_paths() {
+        use crate::PathSegment;
+
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Phone {
+            number: u32,
+        }
+
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Contact {
+            phones: Vec<Phone>,
+            tags: Option<Vec<Shape>>,
+        }
+
+        let input =
+            "%[\n  \"phones\" => [\n    %[\"number\" => 1],\n    %[\"number\" => \"two\"]\n  ]\n]";
+        let err = from_str::<Contact>(input).unwrap_err();
+        assert_eq!(
+            err.path().map(|path| path.segments()),
+            Some(
+                &[
+                    PathSegment::Key("phones".into()),
+                    PathSegment::Index(1),
+                    PathSegment::Key("number".into()),
+                ][..]
+            )
+        );
+        assert_eq!((err.line(), err.column()), (Some(4), Some(19)));
+        assert!(
+            err.to_string()
+                .ends_with(" at phones[1].number, line 4 column 19"),
+            "{err}"
+        );
+
+        let err = from_str::<Contact>(r#"%["phones" => [], "tags" => [%["Circle" => "big"]]]"#)
+            .unwrap_err();
+        assert_eq!(err.path().unwrap().to_string(), "tags[0].Circle");
+        assert_eq!(err.column(), Some(44));
+
+        let value = parse_value(r#"%["phones" => [%["number" => -1]]]"#).unwrap();
+        let err = crate::from_value::<Contact>(value).unwrap_err();
+        assert_eq!(err.path().unwrap().to_string(), "phones[0].number");
+        assert_eq!(err.position(), None);
+
+        let value = parse_value(r#"%["a b" => [1, "x"]]"#).unwrap();
+        let err = crate::from_value::<indexmap::IndexMap<String, Vec<u8>>>(value).unwrap_err();
+        assert_eq!(err.path().unwrap().to_string(), r#"["a b"][1]"#);
+
+        let err = from_str::<Contact>(r#"%["phones" => 3]"#).unwrap_err();
+        assert_eq!(err.path().unwrap().to_string(), "phones");
+        assert_eq!(err.column(), Some(15));
+        assert!(from_str::<u8>("[]").unwrap_err().path().is_none());
+    }
+
+    #[test]
+    fn recovering_parser_reports_every_error() {
+        let input = r#"%[
+            "a" => 1,
+            "b" => 2x,
+            "c" => [1, ?, "\xzz", 4],
+            "d" 5,
+            ?? => 6,
+            "e" => [%["f" => 1] %["g" => true]],
+            "h" => "ok"
+        ]"#;
+        let (value, errors) = crate::parse_value_recovering(input);
+        let lines: Vec<_> = errors.iter().map(|err| err.line().unwrap()).collect();
+        assert_eq!(lines, [3, 4, 4, 5, 6, 7], "{errors:?}");
+        assert!(errors.iter().all(|err| err.snippet().is_some()));
+
+        let expected = parse_value(
+            r#"%[
+                "a" => 1,
+                "b" => 2,
+                "c" => [1, void, void, 4],
+                "d" => void,
+                "e" => [%["f" => 1], %["g" => true]],
+                "h" => "ok"
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(value, expected);
+
+        let (value, errors) = crate::parse_value_recovering("[1, [2, 3");
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].is_eof());
+        assert_eq!(value, parse_value("[1, [2, 3]]").unwrap());
+
+        let (value, errors) = crate::parse_value_recovering("[1, 2] tail");
+        assert_eq!(value, parse_value("[1, 2]").unwrap());
+        assert_eq!(errors.len(), 1);
+
+        let (_, errors) = crate::parse_value_recovering(r#"(const) %["x" => [1, 2]]"#);
+        assert!(errors.is_empty());
+
+        // Recovery must always move forward; run in a thread so that a
+        // regression fails the test instead of hanging it.
+        for input in ["%[%[", "%[ %[a=>1] ]", "%[a => 1 %[b=>2]]"] {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::spawn(move || sender.send(crate::parse_value_recovering(input)));
+            let (_, errors) = receiver
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .unwrap_or_else(|_| panic!("recovery did not terminate on {input:?}"));
+            assert!(!errors.is_empty(), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn edit_document_preserves_formatting() {
+        use crate::edit::Document;
+
+        let input = "// settings\r\nconst %[\r\n  \"name\" : 'kag', /* inline */\r\n  list => [\r\n    0x10,\r\n    2 // two\r\n  ],\r\n  \"flags\" => %[ a => true, ],\r\n]\r\n";
+        let mut doc: Document = input.parse().expect("parse document");
+        assert_eq!(doc.to_string(), input);
+        assert_eq!(doc.to_value(), parse_value(input).unwrap());
+        assert_eq!(doc.get("list[0]").unwrap(), Some(Value::Integer(16)));
+        assert_eq!(doc.get("flags.missing").unwrap(), None);
+
+        doc.set("name", "tjs").unwrap();
+        doc.insert("list[2]", 3_i64).unwrap();
+        doc.insert("list[0]", Value::Null).unwrap();
+        doc.set("flags.b", false).unwrap();
+        doc.set("flags[\"two words\"]", Value::Array(vec![]))
+            .unwrap();
+        assert_eq!(doc.remove("list[1]").unwrap(), Some(Value::Integer(16)));
+        assert_eq!(doc.remove("list[9]").unwrap(), None);
+        assert_eq!(
+            doc.to_string(),
+            "// settings\r\nconst %[\r\n  \"name\" : \"tjs\", /* inline */\r\n  list => [\r\n    null,\r\n    2, // two\r\n    3\r\n  ],\r\n  \"flags\" => %[ a => true, \"b\" => false, \"two words\" => [], ],\r\n]\r\n"
+        );
+
+        let err = doc.set("name.first", 1_i64).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::TypeMismatch);
+        assert_eq!(err.path().unwrap().to_string(), "name");
+        assert!(doc.insert("flags.a", true).is_err());
+        assert!(doc.set("list[5]", true).is_err());
+        assert!(doc.get("list[").is_err());
+
+        let mut doc: Document = "[1, 2, 3]".parse().unwrap();
+        doc.remove("[0]").unwrap();
+        doc.insert("[0]", "x").unwrap();
+        doc.insert("[3]", 4_i64).unwrap();
+        assert_eq!(doc.to_string(), r#"["x", 2, 3, 4]"#);
+    }
+
+    #[test]
+    fn spanned_records_value_locations() {
+        use crate::Spanned;
+
+        #[derive(Debug, Deserialize)]
+        struct Unit {
+            name: Spanned<String>,
+            hp: Spanned<i64>,
+            tags: Option<Spanned<Vec<String>>>,
+        }
+
+        let input = "%[\n  \"name\" => \"slime\",\n  hp => -5,\n  tags => (const) [\"a\", 'b']\n]";
+        let unit: Unit = from_str(input).expect("deserialize");
+        assert_eq!(unit.name.get_ref(), "slime");
+        assert_eq!(&input[unit.name.span()], "\"slime\"");
+        assert_eq!((unit.hp.line(), unit.hp.column()), (3, 9));
+        assert_eq!(&input[unit.hp.span()], "-5");
+        assert_eq!(*unit.hp.get_ref(), -5);
+        let tags = unit.tags.expect("tags");
+        assert_eq!(&input[tags.start()..tags.end()], "[\"a\", 'b']");
+        assert_eq!((tags.line(), tags.column()), (4, 19));
+
+        let whole: Spanned<Vec<i64>> = from_str("  [1, 2] ").unwrap();
+        assert_eq!(whole.span(), 2..8);
+        assert_eq!(whole.into_inner(), vec![1, 2]);
+        let seven: Spanned<i64> = from_str("7").unwrap();
+        assert_eq!(crate::to_string(&seven).unwrap(), "7");
+
+        // Locations are resolved incrementally across many values and lines.
+        let items: Vec<String> = (0..2000)
+            .map(|i| {
+                if i % 7 == 0 {
+                    format!("\n'é{i}'")
+                } else {
+                    format!("'あ{i}'")
+                }
+            })
+            .collect();
+        let input = format!("[{}]", items.join(", "));
+        let spans: Vec<Spanned<String>> = from_str(&input).unwrap();
+        for span in &spans {
+            let expected = crate::error::line_column(&input[..span.start()]);
+            assert_eq!((span.line(), span.column()), expected);
+        }
+
+        let err = from_str::<Unit>("%[name => 1, hp => 2]").unwrap_err();
+        assert_eq!(err.path().unwrap().to_string(), "name");
+        assert!(crate::from_value::<Spanned<i64>>(Value::Integer(1)).is_err());
+    }
+
+    #[test]
+    fn depth_limit_and_deep_values() {
+        use std::str::FromStr;
+
+        use crate::{
+            ErrorKind, ParseOptions, edit, from_str_with_options, from_value,
+            parse_value_with_options,
+        };
+
+        #[derive(Debug, Deserialize)]
+        #[serde(transparent)]
+        struct Nested(#[allow(dead_code)] Vec<Nested>);
+
+        let depth = 100_000;
+        let text = format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        let err = parse_value(&text).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DepthLimitExceeded);
+        assert_eq!(err.column(), Some(129));
+        let err = from_str::<Nested>(&text).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DepthLimitExceeded);
+        let err = edit::Document::from_str(&text).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DepthLimitExceeded);
+
+        let options = ParseOptions {
+            max_depth: Some(2),
+            ..ParseOptions::default()
+        };
+        assert!(parse_value_with_options("[%[a => []]]", &options).is_err());
+        let value: Vec<Vec<i32>> = from_str_with_options("[[1], [2, 3]]", &options).unwrap();
+        assert_eq!(value, vec![vec![1], vec![2, 3]]);
+        let options = ParseOptions {
+            max_depth: None,
+            ..ParseOptions::default()
+        };
+        assert!(parse_value_with_options("[[[[[[]]]]]]", &options).is_ok());
+
+        let mut deep = Value::Array(Vec::new());
+        for _ in 0..depth {
+            deep = Value::Array(vec![deep]);
+        }
+        assert_eq!(deep.to_string().matches('[').count(), depth + 1);
+        let err = from_value::<Nested>(deep).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DepthLimitExceeded);
+
+        // Formatting, cloning, comparing, editing and dropping handle deep
+        // values too.
+        let mut deep = Value::Array(Vec::new());
+        for level in 0..depth {
+            deep = if level % 2 == 0 {
+                Value::Array(vec![deep])
+            } else {
+                Value::Dictionary(indexmap::IndexMap::from([("k".to_owned(), deep)]))
+            };
+        }
+        assert_eq!(deep.to_string().matches('[').count(), depth + 1);
+        let debug = format!("{deep:?}");
+        assert!(debug.starts_with(r#"Dictionary({"k": Array([Dictionary({"#));
+        assert_eq!(debug.matches("Array([").count(), depth / 2 + 1);
+        let copy = deep.clone();
+        assert!(copy == deep);
+        assert!(copy != Value::Dictionary(indexmap::IndexMap::new()));
+        let err = from_value::<i64>(copy).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        assert!(err.to_string().contains("found dictionary"), "{err}");
+        let mut document = edit::Document::from_str("[1]").unwrap();
+        document.insert("[0]", deep.clone()).unwrap();
+        assert_eq!(document.get("[1]").unwrap(), Some(Value::Integer(1)));
+        let text = document.to_string();
+        assert_eq!(text.matches('[').count(), depth + 2);
+        assert!(format!("{document:?}").starts_with("Document(\"[%[\\\"k\\\" => ["));
+        let copy = document.clone();
+        assert_eq!(copy.to_string(), text);
+        assert!(copy.to_value() == Value::Array(vec![deep.clone(), Value::Integer(1)]));
+        let document = edit::Document::from(deep.clone());
+        assert!(document.to_value() == deep);
+        drop(deep);
+    }
+
+    #[test]
+    fn parse_options_limit_resources() {
+        use crate::{ErrorKind, ParseOptions, from_str_with_options, parse_value_with_options};
+
+        /// Buffers whatever it is given, so the streaming deserializer reads
+        /// the whole input.
+        #[derive(Debug, Deserialize)]
+        #[serde(untagged)]
+        enum Buffered {
+            #[allow(dead_code)]
+            Unit(()),
+        }
+
+        let kind = |text: &str, options: &ParseOptions| {
+            let value = parse_value_with_options(text, options).unwrap_err().kind();
+            let streamed = from_str_with_options::<Buffered>(text, options);
+            assert_eq!(streamed.unwrap_err().kind(), value, "{text}");
+            value
+        };
+        let options = ParseOptions {
+            max_input_len: Some(16),
+            max_string_len: Some(3),
+            max_octet_len: Some(2),
+            max_container_len: Some(3),
+            max_nodes: Some(5),
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            kind("[1, 2, 3, 4, 5, 6]", &options),
+            ErrorKind::InputLimitExceeded
+        );
+        assert_eq!(kind("[\"abcd\"]", &options), ErrorKind::StringLimitExceeded);
+        assert_eq!(
+            kind(
+                "%[\"a\\x62cd\" => 1]",
+                &ParseOptions {
+                    max_input_len: None,
+                    ..options.clone()
+                }
+            ),
+            ErrorKind::StringLimitExceeded
+        );
+        assert_eq!(
+            kind("<% 01 02 03 %>", &options),
+            ErrorKind::OctetLimitExceeded
+        );
+        assert_eq!(
+            kind("[1, 2, 3, 4]", &options),
+            ErrorKind::ContainerLimitExceeded
+        );
+        assert_eq!(
+            kind("[[1, 2], [3]]", &options),
+            ErrorKind::NodeLimitExceeded
+        );
+
+        let value = parse_value_with_options("[[1, 2], 'abc']", &options).unwrap();
+        assert_eq!(value.to_string(), "(const) [(const) [1, 2], \"abc\"]");
+        let err = parse_value_with_options("[1, 2, 3, 4]", &options).unwrap_err();
+        assert_eq!(err.column(), Some(11));
+        let err = crate::Deserializer::with_options("[1, 2, 3, 4, 5, 6]", &options).err();
+        assert_eq!(err.unwrap().kind(), ErrorKind::InputLimitExceeded);
+
+        // Byte input is checked before it is read, decoded or inflated.
+        let endless = || std::io::repeat(b' ');
+        let err = crate::parse_value_from_reader_with_options(endless(), &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InputLimitExceeded);
+        let err = crate::from_reader_with_options::<_, Vec<i32>>(endless(), &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InputLimitExceeded);
+        // Readers are buffered whole, so even the default options bound them.
+        let err = crate::from_reader::<_, Vec<i32>>(endless()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InputLimitExceeded);
+        let options = ParseOptions {
+            max_input_len: Some(64),
+            encoding: crate::Encoding::Utf16Le,
+            ..ParseOptions::default()
+        };
+        let utf16 =
+            |text: &str| -> Vec<u8> { text.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+        let value: Vec<i32> = crate::from_slice_with_options(&utf16("[1, 2]"), &options).unwrap();
+        assert_eq!(value, [1, 2]);
+        let value = crate::parse_value_from_reader_with_options(&utf16("[1, 2]")[..], &options);
+        assert_eq!(value.unwrap(), parse_value("[1, 2]").unwrap());
+        let long = utf16(&format!("[{}]", "1, ".repeat(20)));
+        let err = crate::parse_value_from_slice_with_options(&long, &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InputLimitExceeded);
+        let compressed = crate::to_vec_with_options(
+            &"a".repeat(100),
+            &SerializeOptions {
+                storage: crate::Storage::Compressed,
+                ..SerializeOptions::default()
+            },
+        )
+        .unwrap();
+        assert!(compressed.len() <= 64);
+        let err = crate::from_slice_with_options::<String>(&compressed, &options).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InputLimitExceeded);
+        let text: String = crate::from_slice(&compressed).unwrap();
+        assert_eq!(text.len(), 100);
+    }
+
+    #[test]
+    fn duplicate_key_policies() {
+        use crate::{DuplicateKeys, ErrorKind, ParseOptions, parse_value_with_duplicates};
+
+        let text = "%[a => 1, b => 2, a => 3, a => 4]";
+        let parse = |duplicate_keys| {
+            let options = ParseOptions {
+                duplicate_keys,
+                ..ParseOptions::default()
+            };
+            parse_value_with_duplicates(text, &options)
+        };
+
+        let err = parse(DuplicateKeys::Error).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DuplicateKey);
+        assert_eq!(err.column(), Some(19));
+        let cases = [
+            (DuplicateKeys::FirstWins, "%[\"a\" => 1, \"b\" => 2]"),
+            (DuplicateKeys::LastWins, "%[\"a\" => 4, \"b\" => 2]"),
+            (
+                DuplicateKeys::LastWinsMoveToEnd,
+                "%[\"b\" => 2, \"a\" => 4]",
+            ),
+        ];
+        let options = SerializeOptions {
+            const_hint: false,
+            ..SerializeOptions::default()
+        };
+        for (policy, expected) in cases {
+            let (value, duplicates) = parse(policy).unwrap();
+            assert_eq!(value.to_string_with_options(&options), expected);
+            let columns: Vec<_> = duplicates.iter().map(|err| err.column()).collect();
+            assert_eq!(columns, [Some(19), Some(27)]);
+            assert!(
+                duplicates
+                    .iter()
+                    .all(|err| err.kind() == ErrorKind::DuplicateKey)
+            );
+        }
+        assert_eq!(
+            parse_value(text).unwrap(),
+            parse(DuplicateKeys::LastWins).unwrap().0
+        );
+    }
+
+    #[test]
+    fn parse_numeric_literals() {
+        use crate::ErrorKind;
+
+        let parse = |text: &str| parse_value(text).unwrap();
+        assert_eq!(parse("0b1011"), Value::Integer(11));
+        assert_eq!(parse("-0B1"), Value::Integer(-1));
+        assert_eq!(parse("0755"), Value::Integer(0o755));
+        assert_eq!(parse("-010"), Value::Integer(-8));
+        assert_eq!(parse("0"), Value::Integer(0));
+        assert_eq!(parse("0.5"), Value::Real(0.5));
+        assert_eq!(parse("0x1.8p+3"), Value::Real(12.0));
+        assert_eq!(parse("-0x1p-2"), Value::Real(-0.25));
+        assert_eq!(parse("0x.8"), Value::Real(0.5));
+        assert_eq!(parse("0b1.1p1"), Value::Real(3.0));
+        assert_eq!(parse("0x1.fffffffffffffp+1023"), Value::Real(f64::MAX));
+        assert_eq!(parse("0x1p-1074"), Value::Real(f64::from_bits(1)));
+        // Subnormals are rounded once, at their own precision.
+        assert_eq!(
+            parse("0x1.000000000000001p-1075"),
+            Value::Real(f64::from_bits(1))
+        );
+        assert_eq!(parse("0x1p-1075"), Value::Real(0.0));
+        assert_eq!(parse("0x1.8p-1074"), Value::Real(f64::from_bits(2)));
+        assert_eq!(
+            parse("0x0.fffffffffffff8p-1022"),
+            Value::Real(f64::MIN_POSITIVE)
+        );
+        assert_eq!(parse("0x1p-1076"), Value::Real(0.0));
+        assert_eq!(parse("0x1p+1024"), Value::Real(f64::INFINITY));
+        // Digits beyond the precision of an f64 still round correctly.
+        assert_eq!(
+            parse("0x1.00000000000008000000001p0"),
+            Value::Real(1.0 + f64::EPSILON)
+        );
+        assert_eq!(parse("0x1.00000000000008p0"), Value::Real(1.0));
+
+        assert_eq!(parse("0x7fffffffffffffff"), Value::Integer(i64::MAX));
+        assert_eq!(parse("-0x8000000000000000"), Value::Integer(i64::MIN));
+        assert_eq!(parse("0x000000000000000000000000000001"), Value::Integer(1));
+        let kind = |text: &str| parse_value(text).unwrap_err().kind();
+        assert_eq!(kind("0x8000000000000000"), ErrorKind::Overflow);
+        assert_eq!(kind("-0x8000000000000001"), ErrorKind::Overflow);
+        assert_eq!(kind("01777777777777777777777"), ErrorKind::Overflow);
+        assert_eq!(kind(&format!("0b1{}", "0".repeat(63))), ErrorKind::Overflow);
+        assert_eq!(kind("0b102"), ErrorKind::Syntax);
+        assert_eq!(kind("089"), ErrorKind::Syntax);
+        assert_eq!(kind("0x"), ErrorKind::Eof);
+        assert_eq!(kind("0x1p"), ErrorKind::Eof);
+    }
+
+    #[test]
+    fn float_styles() {
+        use crate::FloatStyle;
+
+        let print = |value: f64, float_style| {
+            let options = SerializeOptions {
+                float_style,
+                ..SerializeOptions::default()
+            };
+            crate::to_string_with_options(&value, &options).expect("serialize")
+        };
+        let scientific = FloatStyle::Scientific {
+            lower: 1e-6,
+            upper: 1e21,
+        };
+        assert_eq!(print(3e20, scientific), "300000000000000000000.0");
+        assert_eq!(print(3e21, scientific), "3e21");
+        assert_eq!(print(-1.5e-7, scientific), "-1.5e-7");
+        assert_eq!(print(0.25, scientific), "0.25");
+        assert_eq!(print(0.0, scientific), "0.0");
+
+        let cases = [
+            (12.0, "0x1.8p+3"),
+            (1.0, "0x1p+0"),
+            (-0.1, "-0x1.999999999999ap-4"),
+            (0.0, "0x0p+0"),
+            (-0.0, "-0x0p+0"),
+            (f64::MAX, "0x1.fffffffffffffp+1023"),
+            (f64::MIN_POSITIVE, "0x1p-1022"),
+            (f64::from_bits(1), "0x0.0000000000001p-1022"),
+            (f64::NEG_INFINITY, "-Infinity"),
+        ];
+        for (value, expected) in cases {
+            let text = print(value, FloatStyle::HexFloat);
+            assert_eq!(text, expected);
+            let parsed = parse_value(&text).expect("parse");
+            assert!(matches!(parsed, Value::Real(num) if num.to_bits() == value.to_bits()));
+        }
+
+        assert_eq!(crate::to_string(&0.1f32).expect("serialize"), "0.1");
+        assert_eq!(from_str::<f32>("0.1").expect("parse"), 0.1f32);
+        // Only decimal notation is shortened; the value itself is kept.
+        let print_f32 = |value: f32, float_style| {
+            let options = SerializeOptions {
+                float_style,
+                ..SerializeOptions::default()
+            };
+            crate::to_string_with_options(&value, &options).expect("serialize")
+        };
+        assert_eq!(print_f32(3e21, scientific), "3e21");
+        assert_eq!(print_f32(0.1, FloatStyle::HexFloat), "0x1.99999ap-4");
+        // A `Value` holds every real as an `f64`, so it prints all its digits.
+        let value = crate::to_value(0.1f32).unwrap();
+        assert_eq!(value, Value::Real(f64::from(0.1f32)));
+        assert_eq!(value.to_string(), "0.10000000149011612");
+        assert_eq!(from_str::<f32>(&value.to_string()).expect("parse"), 0.1f32);
+    }
+
+    #[test]
+    fn string_escapes() {
+        use crate::{ErrorKind, ParseOptions, parse_value_with_options};
+
+        let parse = |text: &str| from_str::<String>(text).expect("parse");
+        assert_eq!(parse(r#""\a\b\f\n\r\t\v""#), "\x07\x08\x0c\n\r\t\x0b");
+        assert_eq!(parse(r#""\x41\x4a1\X7""#), "A\u{4a1}\x07");
+        assert_eq!(parse(r#""\x41g""#), "Ag");
+        assert_eq!(parse(r#""\0\101\0123\7""#), "\0A\n3\x07");
+        assert_eq!(parse(r#""😀\xd83d\xde00""#), "\u{1f600}\u{1f600}");
+        assert_eq!(parse(r#""\q\'""#), "q'");
+
+        let kind = |text: &str| parse_value(text).unwrap_err().kind();
+        assert_eq!(kind(r#""\ud83d""#), ErrorKind::InvalidEscape);
+        assert_eq!(kind(r#""\ud83dx""#), ErrorKind::InvalidEscape);
+        assert_eq!(kind(r#""\ude00\ud83d""#), ErrorKind::InvalidEscape);
+        assert_eq!(kind(r#""\x""#), ErrorKind::InvalidEscape);
+
+        let strict = ParseOptions {
+            strict_escapes: true,
+            ..ParseOptions::default()
+        };
+        let err = parse_value_with_options(r#"["ok\v", "\q"]"#, &strict).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidEscape);
+        assert_eq!(err.column(), Some(11));
+
+        let text = "\x01a\x02\x07\x0b\u{1f600}";
+        let written = Value::String(text.to_owned()).to_string();
+        assert_eq!(written, r#""\x0001a\x02\a\v😀""#);
+        assert_eq!(parse(&written), text);
+    }
+
+    #[test]
+    fn joins_adjacent_strings() {
+        let text = r#"%[
+            text => "line one\n" // first
+                "line two",
+            "ab" + 'c' => ["x" + /* joined */ "y", "z"],
+        ]"#;
+        let value = parse_value(text).expect("parse");
+        assert_eq!(
+            value.to_string(),
+            r#"(const) %["text" => "line one\nline two", "abc" => (const) ["xy", "z"]]"#
+        );
+        assert_eq!(
+            from_str::<Vec<String>>(r#"["a" "b", 'c']"#).unwrap(),
+            ["ab", "c"]
+        );
+        let borrowed: Vec<&str> = from_str(r#"["a", "b" ]"#).unwrap();
+        assert_eq!(borrowed, ["a", "b"]);
+
+        let err = parse_value(r#"["a" + 1]"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected string after '+' at line 1 column 8"
+        );
+    }
+
+    #[test]
+    fn streaming_serializer_matches_value_path() {
+        use std::collections::BTreeMap;
+
+        use serde::Serializer as _;
+
+        use crate::{FloatStyle, Serializer, to_value};
+
+        #[derive(Serialize)]
+        enum Item {
+            Unit,
+            Newtype(Vec<u8>),
+            #[serde(with = "serde_bytes_like")]
+            Bytes(Vec<u8>),
+            Tuple(i8, Option<u64>),
+            Struct {
+                x: f32,
+                tags: BTreeMap<u32, char>,
+            },
+        }
+
+        mod serde_bytes_like {
+            pub fn serialize<S: serde::Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+                s.serialize_bytes(v)
+            }
+        }
+
+        let items = vec![
+            Item::Unit,
+            Item::Newtype(vec![]),
+            Item::Bytes(vec![0xde, 0xad]),
+            Item::Tuple(-1, Some(u64::MAX)),
+            Item::Tuple(2, None),
+            Item::Struct {
+                x: 0.5,
+                tags: BTreeMap::from([(1, 'a'), (20, '"')]),
+            },
+        ];
+        let value = to_value(&items).expect("to_value");
+        let pretty = SerializeOptions {
+            indent: Some(2),
+            float_style: FloatStyle::HexFloat,
+            ..SerializeOptions::default()
+        };
+        let compact = SerializeOptions {
+            const_hint: false,
+            ..SerializeOptions::default()
+        };
+        for options in [pretty, compact] {
+            let streamed = crate::to_string_with_options(&items, &options).expect("serialize");
+            assert_eq!(streamed, value.to_string_with_options(&options));
+        }
+
+        let mut serializer = Serializer::new(Vec::new());
+        serializer.collect_seq([(); 2]).expect("serialize");
+        assert_eq!(serializer.into_inner(), b"(const) [void, void]");
+
+        struct Failing;
+        impl std::io::Write for Failing {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disk full"))
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let err = crate::to_writer(Failing, &items).unwrap_err();
+        assert!(err.is_io());
+        let mut map = BTreeMap::new();
+        map.insert(vec![1], 2);
+        let err = crate::to_writer(Vec::new(), &map).unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn layout_options() {
+        use crate::{Newline, Quote, to_value};
+
+        struct Bytes(&'static [u8]);
+        impl Serialize for Bytes {
+            fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                s.serialize_bytes(self.0)
+            }
+        }
+
+        #[derive(Serialize)]
+        struct Entry {
+            name: &'static str,
+            id: i32,
+            data: Bytes,
+            list: Vec<i32>,
+        }
+
+        #[derive(Serialize)]
+        enum Shape {
+            Rect { w: i32, height: i32 },
+        }
+
+        let entry = Entry {
+            name: "it's",
+            id: 7,
+            data: Bytes(&[1, 2, 3]),
+            list: vec![1],
+        };
+        let options = SerializeOptions {
+            const_hint: false,
+            indent: Some(1),
+            use_tabs: true,
+            newline: Newline::CrLf,
+            trailing_comma: true,
+            quote: Quote::Single,
+            align_arrows: true,
+            octet_line_len: Some(2),
+            ..SerializeOptions::default()
+        };
+        let expected = "%[\r\n\t'name' => 'it\\'s',\r\n\t'id'   => 7,\r\n\
+                        \t'data' => <%\r\n\t\t01 02\r\n\t\t03\r\n\t%>,\r\n\
+                        \t'list' => [\r\n\t\t1,\r\n\t],\r\n]";
+        let streamed = crate::to_string_with_options(&entry, &options).expect("serialize");
+        assert_eq!(streamed, expected);
+        let value = to_value(&entry).expect("to_value");
+        assert_eq!(value.to_string_with_options(&options), expected);
+
+        let shape = Shape::Rect { w: 1, height: 2 };
+        let aligned = SerializeOptions {
+            const_hint: false,
+            indent: Some(2),
+            align_arrows: true,
+            ..SerializeOptions::default()
+        };
+        let expected = "%[\n  \"Rect\" => %[\n    \"w\"      => 1,\n    \"height\" => 2\n  ]\n]";
+        let streamed = crate::to_string_with_options(&shape, &aligned).expect("serialize");
+        assert_eq!(streamed, expected);
+        let value = to_value(&shape).expect("to_value");
+        assert_eq!(value.to_string_with_options(&aligned), expected);
+
+        // Compact output ignores the pretty-printing options.
+        let compact = SerializeOptions {
+            const_hint: false,
+            space_before_arrow: false,
+            trailing_comma: true,
+            align_arrows: true,
+            ..SerializeOptions::default()
+        };
+        assert_eq!(
+            crate::to_string_with_options(&shape, &compact).expect("serialize"),
+            "%[\"Rect\"=> %[\"w\"=> 1, \"height\"=> 2]]"
+        );
+        let compact = SerializeOptions {
+            octet_line_len: Some(2),
+            ..compact
+        };
+        assert_eq!(
+            crate::to_string_with_options(&[Bytes(&[1, 2, 3, 4, 5])], &compact).expect("serialize"),
+            "[<% 01 02 03 04 05 %>]"
+        );
+        let value = Value::Array(vec![Value::Octet(vec![1, 2, 3, 4, 5])]);
+        assert_eq!(
+            value.to_string_with_options(&compact),
+            "[<% 01 02 03 04 05 %>]"
+        );
+    }
+
+    #[test]
+    fn max_width_keeps_short_containers_inline() {
+        use crate::to_value;
+
+        #[derive(Serialize)]
+        enum Pos {
+            At(i32, i32),
+        }
+
+        #[derive(Serialize)]
+        struct Scene {
+            name: &'static str,
+            points: Vec<[i32; 2]>,
+            origin: Pos,
+        }
+
+        let scene = Scene {
+            name: "demo",
+            points: vec![[0, 0], [10, 20], [30, 40]],
+            origin: Pos::At(1, 2),
+        };
+        let options = SerializeOptions {
+            const_hint: false,
+            indent: Some(2),
+            max_width: Some(40),
+            ..SerializeOptions::default()
+        };
+        let expected = "%[\n  \"name\" => \"demo\",\n  \"points\" => [\n    [0, 0],\n    \
+                        [10, 20],\n    [30, 40]\n  ],\n  \"origin\" => %[\"At\" => [1, 2]]\n]";
+        let streamed = crate::to_string_with_options(&scene, &options).expect("serialize");
+        assert_eq!(streamed, expected);
+        let value = to_value(&scene).expect("to_value");
+        assert_eq!(value.to_string_with_options(&options), expected);
+
+        assert_eq!(
+            crate::to_string_with_options(&[1, 2], &options).expect("serialize"),
+            "[1, 2]"
+        );
+
+        // The comma after an item counts towards the width of its line.
+        let pairs = [[1, 2], [3, 4]];
+        let options = SerializeOptions {
+            max_width: Some(9),
+            ..options
+        };
+        assert_eq!(
+            crate::to_string_with_options(&pairs, &options).expect("serialize"),
+            "[\n  [1, 2],\n  [3, 4]\n]"
+        );
+        let options = SerializeOptions {
+            max_width: Some(8),
+            ..options
+        };
+        assert_eq!(
+            crate::to_string_with_options(&pairs, &options).expect("serialize"),
+            "[\n  [\n    1,\n    2\n  ],\n  [3, 4]\n]"
+        );
+        let options = SerializeOptions {
+            trailing_comma: true,
+            ..options
+        };
+        assert_eq!(
+            to_value(pairs)
+                .expect("to_value")
+                .to_string_with_options(&options),
+            "[\n  [\n    1,\n    2,\n  ],\n  [\n    3,\n    4,\n  ],\n]"
+        );
+    }
+
+    #[test]
+    fn kirikiri_preset_matches_save_struct() {
+        use std::collections::BTreeMap;
+
+        use crate::{FloatStyle, Value, parse_value, parse_value_from_slice, to_value};
+
+        struct Bytes(&'static [u8]);
+        impl Serialize for Bytes {
+            fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                s.serialize_bytes(self.0)
+            }
+        }
+
+        #[derive(Serialize)]
+        struct Save {
+            name: &'static str,
+            level: i32,
+            ratio: f64,
+            tags: (&'static str, f64),
+            zero: f64,
+            empty: Vec<i32>,
+            data: Bytes,
+            blank: Bytes,
+            none: Option<i32>,
+            nested: BTreeMap<String, i32>,
+        }
+
+        let dictionary = Save {
+            name: "Alice \"A\"",
+            level: 3,
+            ratio: 0.1,
+            tags: ("a", -2.0),
+            zero: 0.0,
+            empty: vec![],
+            data: Bytes(&[0x01, 0xab, 0xff]),
+            blank: Bytes(&[]),
+            none: None,
+            nested: BTreeMap::new(),
+        };
+        let array = (
+            1.5,
+            1e100,
+            1.0 / 3.0,
+            123456789.125,
+            2.5e-7,
+            0.0,
+            "文字列",
+            (1, BTreeMap::from([("x", 0)])),
+        );
+
+        // Parity with the engine itself is unverified: the golden files were
+        // not saved by Kirikiri but written by tests/data/generate_kirikiri.py,
+        // a port of the engine's algorithm made independently of this crate.
+        // Replace them with files saved by the engine when possible.
+        let options = SerializeOptions::kirikiri();
+        let golden: [&[u8]; 2] = [
+            include_bytes!("../tests/data/kirikiri_dictionary.tjs"),
+            include_bytes!("../tests/data/kirikiri_array.tjs"),
+        ];
+        let written = [
+            crate::to_vec_with_options(&dictionary, &options).expect("serialize"),
+            crate::to_vec_with_options(&array, &options).expect("serialize"),
+        ];
+        let values = [
+            to_value(&dictionary).expect("to_value"),
+            to_value(&array).expect("to_value"),
+        ];
+        let lossless = SerializeOptions {
+            float_style: FloatStyle::KirikiriLossless,
+            ..options.clone()
+        };
+        for ((golden, written), expected) in golden.into_iter().zip(written).zip(values) {
+            assert_eq!(written, golden);
+            let units: Vec<u16> = golden[2..]
+                .chunks(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            let text = String::from_utf16(&units).expect("UTF-16");
+            // Kirikiri's zero reads back as 2^-1023.
+            let value = parse_value_from_slice(golden).expect("parse golden file");
+            assert_eq!(
+                value.to_string_with_options(&options),
+                text.replace(
+                    "0x1.0000000000000p-1023 /* 0 */",
+                    "0x1.8000000000000p-1023 /* 1.1125369292536e-308 */"
+                )
+            );
+
+            let lossless_text = expected.to_string_with_options(&lossless);
+            assert_eq!(
+                lossless_text,
+                text.replace("0x1.0000000000000p-1023", "0x0.0000000000000p0")
+            );
+            assert_eq!(parse_value(&lossless_text).expect("parse"), expected);
+        }
+
+        let tiny = f64::from_bits(1);
+        assert_eq!(
+            Value::Real(tiny).to_string_with_options(&options),
+            "0x1.0000000000001p-1023 /* 4.94065645841247e-324 */"
+        );
+        let text = Value::Real(-tiny).to_string_with_options(&lossless);
+        assert_eq!(
+            text,
+            "-0x0.0000000000001p-1022 /* -4.94065645841247e-324 */"
+        );
+        assert_eq!(crate::from_str::<f64>(&text).expect("parse"), -tiny);
+    }
+
+    #[test]
+    fn key_order_is_deterministic() {
+        use std::collections::HashMap;
+
+        use crate::{KeyOrder, Serializer, to_value};
+
+        let keys = ["item10", "item2", "b", "item02", "a"];
+        let inner: HashMap<&str, i32> = keys.iter().map(|key| (*key, 0)).collect();
+        let outer = HashMap::from([("z", inner.clone()), ("y", inner)]);
+
+        let natural = SerializeOptions {
+            const_hint: false,
+            key_order: KeyOrder::Natural,
+            ..SerializeOptions::default()
+        };
+        let inner_sorted = r#"%["a" => 0, "b" => 0, "item02" => 0, "item2" => 0, "item10" => 0]"#;
+        let expected = format!(r#"%["y" => {inner_sorted}, "z" => {inner_sorted}]"#);
+        let streamed = crate::to_string_with_options(&outer, &natural).expect("serialize");
+        assert_eq!(streamed, expected);
+
+        // Only dictionaries are collected, so an array is written up to the
+        // item that fails.
+        struct Failing;
+        impl Serialize for Failing {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("failed"))
+            }
+        }
+        let mut serializer = Serializer::with_options(Vec::new(), &natural);
+        (&outer["y"], Failing)
+            .serialize(&mut serializer)
+            .expect_err("serialize");
+        assert_eq!(
+            serializer.into_inner(),
+            format!("[{inner_sorted}, ").into_bytes()
+        );
+
+        let mut value = to_value(&outer).expect("to_value");
+        assert_eq!(value.to_string_with_options(&natural), expected);
+
+        let lexicographic = SerializeOptions {
+            key_order: KeyOrder::Lexicographic,
+            ..natural
+        };
+        let inner_sorted = r#"%["a" => 0, "b" => 0, "item02" => 0, "item10" => 0, "item2" => 0]"#;
+        let expected = format!(r#"%["y" => {inner_sorted}, "z" => {inner_sorted}]"#);
+        let streamed = crate::to_string_with_options(&outer, &lexicographic).expect("serialize");
+        assert_eq!(streamed, expected);
+
+        value.sort_keys_recursive();
+        let preserve = SerializeOptions {
+            key_order: KeyOrder::Preserve,
+            ..lexicographic
+        };
+        assert_eq!(value.to_string_with_options(&preserve), expected);
+    }
+
     #[test]
     fn const_hint_toggle() {
         let value = Value::Array(vec![Value::Integer(1), Value::Integer(2)]);
         let mut options = SerializeOptions {
             const_hint: true,
             indent: None,
+            ..SerializeOptions::default()
         };
         let with_const = value.to_string_with_options(&options);
         assert!(with_const.starts_with("(const)"));
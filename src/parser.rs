@@ -1,43 +1,294 @@
+use std::borrow::Cow;
+
 use indexmap::IndexMap;
 
-use crate::error::{Error, Result};
+use crate::encoding::Encoding;
+use crate::error::{Error, ErrorKind, Result};
 use crate::value::Value;
 
+/// Default limit on how deeply arrays and dictionaries may be nested.
+const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Default limit on the size of the input in bytes.
+const DEFAULT_MAX_INPUT_LEN: usize = 64 * 1024 * 1024;
+
+/// Options used when parsing or deserializing TJS2 text.
+///
+/// Every limit can be disabled with `None`. Only the nesting depth and the
+/// input size are limited by default; set the other limits when reading
+/// untrusted input.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Maximum nesting depth of arrays and dictionaries.
+    ///
+    /// Parsing is recursive, so input nested deeper than the stack allows
+    /// crashes the process when there is no limit. Defaults to 128.
+    pub max_depth: Option<usize>,
+    /// Maximum size of the input in bytes.
+    ///
+    /// The byte-oriented entry points check the size before reading or
+    /// decoding the input, and the uncompressed size of compressed Kirikiri
+    /// text before inflating it. The reader entry points hold the whole input
+    /// in memory, so this also bounds how much they buffer. Defaults to 64 MiB.
+    pub max_input_len: Option<usize>,
+    /// Maximum length of a string literal or dictionary key in bytes, after
+    /// escape sequences have been decoded.
+    pub max_string_len: Option<usize>,
+    /// Maximum number of bytes in an octet literal.
+    pub max_octet_len: Option<usize>,
+    /// Maximum number of elements in an array or entries in a dictionary.
+    pub max_container_len: Option<usize>,
+    /// Maximum number of values in the whole input, counting containers and
+    /// everything inside them.
+    pub max_nodes: Option<usize>,
+    /// What to do when a dictionary contains the same key more than once.
+    ///
+    /// This applies when parsing into a [`Value`]; other types decide for
+    /// themselves when deserialized with [`from_str`](crate::from_str).
+    pub duplicate_keys: DuplicateKeys,
+    /// Whether to reject escape sequences TJS2 does not define, such as `\q`.
+    ///
+    /// By default the backslash is dropped and the character kept, as TJS2
+    /// itself does.
+    pub strict_escapes: bool,
+    /// Encoding of input without a byte order mark or a Kirikiri storage
+    /// header, used by the byte-oriented entry points (`*from_slice*` and
+    /// `*from_reader*`).
+    pub encoding: Encoding,
+}
+
+/// How a dictionary that repeats a key is parsed into a [`Value`].
+///
+/// [`parse_value_with_duplicates`](crate::parse_value_with_duplicates)
+/// reports every duplicate key that was resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKeys {
+    /// Fail with [`ErrorKind::DuplicateKey`].
+    Error,
+    /// Keep the first value and ignore later ones.
+    FirstWins,
+    /// Keep the last value, at the position of the first occurrence of the key.
+    #[default]
+    LastWins,
+    /// Keep the last value, at the position of the last occurrence of the key.
+    LastWinsMoveToEnd,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_input_len: Some(DEFAULT_MAX_INPUT_LEN),
+            max_string_len: None,
+            max_octet_len: None,
+            max_container_len: None,
+            max_nodes: None,
+            duplicate_keys: DuplicateKeys::default(),
+            strict_escapes: false,
+            encoding: Encoding::default(),
+        }
+    }
+}
+
+/// Returns `true` if `len` is over `limit`.
+fn exceeds(len: usize, limit: Option<usize>) -> bool {
+    limit.is_some_and(|limit| len > limit)
+}
+
+/// Fails if an input of `len` bytes is larger than `limit`, as given by
+/// [`ParseOptions::max_input_len`].
+pub(crate) fn check_input_len(len: usize, limit: Option<usize>) -> Result<()> {
+    match limit {
+        Some(limit) if len > limit => Err(Error::new(
+            ErrorKind::InputLimitExceeded,
+            format!("input exceeds the size limit of {limit} bytes"),
+        )),
+        _ => Ok(()),
+    }
+}
+
 pub fn parse_str(input: &str) -> Result<Value> {
-    let mut parser = Parser::new(input);
+    parse_str_with_options(input, &ParseOptions::default())
+}
+
+pub fn parse_str_with_options(input: &str, options: &ParseOptions) -> Result<Value> {
+    parse_str_with_duplicates(input, options).map(|(value, _)| value)
+}
+
+/// Parses `input`, also returning an error of kind [`ErrorKind::DuplicateKey`]
+/// for every duplicate dictionary key resolved by the policy in `options`.
+pub fn parse_str_with_duplicates(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<(Value, Vec<Error>)> {
+    check_input_len(input.len(), options.max_input_len)?;
+    parse_checked(input, options)
+}
+
+/// Like [`parse_str_with_duplicates`], for text whose size has already been
+/// checked, such as text decoded from bytes that were checked instead.
+pub(crate) fn parse_checked(input: &str, options: &ParseOptions) -> Result<(Value, Vec<Error>)> {
+    let mut parser = Parser::with_options(input, options);
     parser.skip_ws()?;
     let value = parser.parse_value()?;
     parser.skip_ws()?;
     if parser.is_eof() {
-        Ok(value)
+        Ok((value, parser.duplicates))
     } else {
-        Err(Error::with_position(
-            "unexpected trailing characters",
-            parser.position,
-        ))
+        Err(parser.error(ErrorKind::Syntax, "unexpected trailing characters"))
     }
 }
 
-struct Parser<'a> {
+/// Parses `input`, recording syntax errors instead of stopping at the first
+/// one. Values that cannot be read are replaced by [`Value::Void`].
+pub fn parse_str_recovering(input: &str) -> (Value, Vec<Error>) {
+    let mut parser = Parser::new(input);
+    parser.errors = Some(Vec::new());
+    // In recovery mode `recover` hands out the placeholder instead of failing.
+    let parsed = parser.skip_ws().and_then(|()| parser.parse_value());
+    let value = parser.recover(parsed, Value::Void).unwrap_or(Value::Void);
+    let rest = parser.skip_ws().and_then(|()| {
+        if parser.is_eof() {
+            Ok(())
+        } else {
+            Err(parser.error(ErrorKind::Syntax, "unexpected trailing characters"))
+        }
+    });
+    parser.recover(rest, ()).ok();
+    (value, parser.errors.unwrap_or_default())
+}
+
+pub(crate) struct Parser<'a> {
     input: &'a str,
     bytes: &'a [u8],
     position: usize,
+    limits: ParseOptions,
+    /// How many more levels of arrays and dictionaries may be entered.
+    remaining_depth: Option<usize>,
+    /// Number of values read so far, including the one being read.
+    nodes: usize,
+    /// Duplicate dictionary keys seen so far.
+    duplicates: Vec<Error>,
+    /// Errors recorded so far when parsing in recovery mode.
+    errors: Option<Vec<Error>>,
+    /// The last position resolved by [`Parser::line_column`] with its line
+    /// and column, from which the next lookup continues.
+    located: (usize, usize, usize),
 }
 
 impl<'a> Parser<'a> {
-    fn new(input: &'a str) -> Self {
+    pub(crate) fn new(input: &'a str) -> Self {
+        Self::with_options(input, &ParseOptions::default())
+    }
+
+    pub(crate) fn with_options(input: &'a str, options: &ParseOptions) -> Self {
         Self {
             input,
             bytes: input.as_bytes(),
             position: 0,
+            limits: options.clone(),
+            remaining_depth: options.max_depth,
+            nodes: 1,
+            duplicates: Vec::new(),
+            errors: None,
+            located: (0, 1, 1),
+        }
+    }
+
+    /// Enters an array or dictionary starting at the current position.
+    pub(crate) fn enter(&mut self) -> Result<()> {
+        match &mut self.remaining_depth {
+            Some(0) => Err(self.error(
+                ErrorKind::DepthLimitExceeded,
+                "nesting depth limit exceeded",
+            )),
+            Some(remaining) => {
+                *remaining -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Leaves an array or dictionary entered with [`Parser::enter`].
+    pub(crate) fn leave(&mut self) {
+        if let Some(remaining) = &mut self.remaining_depth {
+            *remaining += 1;
+        }
+    }
+
+    /// Counts the element of an array or dictionary starting at the current
+    /// position, `len` being the number of elements including this one.
+    pub(crate) fn add_element(&mut self, len: usize) -> Result<()> {
+        if exceeds(len, self.limits.max_container_len) {
+            return Err(self.error(
+                ErrorKind::ContainerLimitExceeded,
+                "too many elements in array or dictionary",
+            ));
+        }
+        self.nodes += 1;
+        if exceeds(self.nodes, self.limits.max_nodes) {
+            return Err(self.error(ErrorKind::NodeLimitExceeded, "too many values in input"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the 1-based line and column of `position`. Lookups continue
+    /// from the previous one, so positions visited in order are resolved in
+    /// a single pass over the input.
+    pub(crate) fn line_column(&mut self, position: usize) -> (usize, usize) {
+        let (mut start, mut line, mut column) = self.located;
+        if position < start {
+            (start, line, column) = (0, 1, 1);
+        }
+        let scanned = &self.input[start..position];
+        match scanned.rfind('\n') {
+            Some(idx) => {
+                line += scanned.matches('\n').count();
+                column = scanned[idx + 1..].chars().count() + 1;
+            }
+            None => column += scanned.chars().count(),
         }
+        self.located = (position, line, column);
+        (line, column)
     }
 
-    fn is_eof(&self) -> bool {
+    /// Resolves an error raised while reading the value that starts at
+    /// `position`, unless it already carries a more precise location.
+    pub(crate) fn locate(&self, err: Error, position: usize) -> Error {
+        err.or_position(position).with_source(self.input)
+    }
+
+    /// Creates an error at the current position, located within the input.
+    pub(crate) fn error(&self, kind: ErrorKind, message: impl Into<String>) -> Error {
+        self.error_at(kind, message, self.position)
+    }
+
+    /// Creates an error at `position`, located within the input. Syntax errors
+    /// at the very end of the input are reported as [`ErrorKind::Eof`].
+    pub(crate) fn error_at(
+        &self,
+        kind: ErrorKind,
+        message: impl Into<String>,
+        position: usize,
+    ) -> Error {
+        let kind = if kind == ErrorKind::Syntax && position >= self.bytes.len() {
+            ErrorKind::Eof
+        } else {
+            kind
+        };
+        Error::with_position(kind, message, position).with_source(self.input)
+    }
+
+    pub(crate) fn is_eof(&self) -> bool {
         self.position >= self.bytes.len()
     }
 
-    fn skip_ws(&mut self) -> Result<()> {
+    pub(crate) fn skip_ws(&mut self) -> Result<()> {
         loop {
             let Some(ch) = self.peek_char() else {
                 return Ok(());
@@ -59,10 +310,7 @@ impl<'a> Parser<'a> {
                     if let Some(idx) = rest.find("*/") {
                         self.position += idx + 2;
                     } else {
-                        return Err(Error::with_position(
-                            "unterminated block comment",
-                            self.position,
-                        ));
+                        return Err(self.error(ErrorKind::Eof, "unterminated block comment"));
                     }
                     continue;
                 }
@@ -71,24 +319,106 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_value(&mut self) -> Result<Value> {
+    /// Skips whitespace, comments and `(const)` hints in front of a value and
+    /// returns the first byte of the value itself.
+    pub(crate) fn peek_value_start(&mut self) -> Result<Option<u8>> {
         self.skip_ws()?;
         self.consume_const_hint();
         self.skip_ws()?;
-        match self.peek_byte() {
-            Some(b'[') => self.parse_array(),
-            Some(b'%') => self.parse_dictionary(),
-            Some(b'"') | Some(b'\'') => self.parse_string().map(Value::String),
+        Ok(self.peek_byte())
+    }
+
+    pub(crate) fn parse_value(&mut self) -> Result<Value> {
+        match self.peek_value_start()? {
+            Some(b'[') | Some(b'%') => {
+                self.enter()?;
+                let value = if self.peek_byte() == Some(b'[') {
+                    self.parse_array()
+                } else {
+                    self.parse_dictionary()
+                };
+                self.leave();
+                value
+            }
+            Some(b'"') | Some(b'\'') => {
+                let start = self.position;
+                self.parse_string()
+                    .map(|text| Value::String(text.into_owned()))
+                    // Lets recovery skip the literal as a whole.
+                    .inspect_err(|_| self.position = start)
+            }
             Some(b'<') if self.starts_with("<%") => self.parse_octet(),
             Some(b't') | Some(b'f') | Some(b'n') | Some(b'v') | Some(b'I') | Some(b'N') => {
                 self.parse_literal()
             }
             Some(b'+') | Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
-            Some(_) => Err(Error::with_position("unexpected token", self.position)),
-            None => Err(Error::with_position(
-                "unexpected end of input",
-                self.position,
-            )),
+            Some(_) => Err(self.error(ErrorKind::Syntax, "unexpected token")),
+            None => Err(self.error(ErrorKind::Eof, "unexpected end of input")),
+        }
+    }
+
+    /// Reads a value like [`Parser::parse_value`], checking its syntax and
+    /// the limits, without building it.
+    pub(crate) fn skip_value(&mut self) -> Result<()> {
+        match self.peek_value_start()? {
+            Some(b'[') | Some(b'%') => {
+                self.enter()?;
+                let skipped = if self.peek_byte() == Some(b'[') {
+                    self.skip_array()
+                } else {
+                    self.skip_dictionary()
+                };
+                self.leave();
+                skipped
+            }
+            Some(b'"') | Some(b'\'') => self.skip_string(),
+            Some(b'<') if self.starts_with("<%") => self.read_octet(|_| {}),
+            Some(b't') | Some(b'f') | Some(b'n') | Some(b'v') | Some(b'I') | Some(b'N') => {
+                self.parse_literal().map(drop)
+            }
+            Some(b'+') | Some(b'-') | Some(b'0'..=b'9') => self.parse_number().map(drop),
+            Some(_) => Err(self.error(ErrorKind::Syntax, "unexpected token")),
+            None => Err(self.error(ErrorKind::Eof, "unexpected end of input")),
+        }
+    }
+
+    fn skip_array(&mut self) -> Result<()> {
+        self.expect_char('[')?;
+        self.skip_container(Self::skip_value)
+    }
+
+    fn skip_dictionary(&mut self) -> Result<()> {
+        self.expect_char('%')?;
+        self.skip_ws()?;
+        self.expect_char('[')?;
+        self.skip_container(|parser| {
+            parser.skip_dict_key()?;
+            parser.parse_key_separator()?;
+            parser.skip_value()
+        })
+    }
+
+    /// Skips the items of an array or dictionary after its opening bracket,
+    /// up to and including the closing one.
+    fn skip_container<F>(&mut self, mut skip_item: F) -> Result<()>
+    where
+        F: FnMut(&mut Self) -> Result<()>,
+    {
+        let mut len = 0;
+        loop {
+            self.skip_ws()?;
+            if self.consume_ascii(']') {
+                return Ok(());
+            }
+            len += 1;
+            self.add_element(len)?;
+            skip_item(self)?;
+            self.skip_ws()?;
+            if self.consume_ascii(']') {
+                return Ok(());
+            } else if !self.consume_ascii(',') {
+                return Err(self.error(ErrorKind::Syntax, "expected ',' or ']'"));
+            }
         }
     }
 
@@ -96,19 +426,17 @@ impl<'a> Parser<'a> {
         self.expect_char('[')?;
         let mut items = Vec::new();
         loop {
-            self.skip_ws()?;
+            let skipped = self.skip_ws();
+            self.recover(skipped, ())?;
             if self.consume_ascii(']') {
                 break;
             }
-            let value = self.parse_value()?;
-            items.push(value);
-            self.skip_ws()?;
-            if self.consume_ascii(',') {
-                continue;
-            } else if self.consume_ascii(']') {
+            self.add_element(items.len() + 1)?;
+            let entry_start = self.position;
+            let value = self.parse_value();
+            items.push(self.recover(value, Value::Void)?);
+            if self.end_of_entry(entry_start)? {
                 break;
-            } else {
-                return Err(Error::with_position("expected ',' or ']'", self.position));
             }
         }
         Ok(Value::Array(items))
@@ -120,46 +448,193 @@ impl<'a> Parser<'a> {
         self.expect_char('[')?;
         let mut entries = IndexMap::new();
         loop {
-            self.skip_ws()?;
+            let skipped = self.skip_ws();
+            self.recover(skipped, ())?;
             if self.consume_ascii(']') {
                 break;
             }
-            let key = self.parse_dict_key()?;
-            self.skip_ws()?;
-            if self.consume_exact("=>") {
-                // ok
-            } else if self.consume_ascii(':') {
-                // legacy form
-            } else {
-                return Err(Error::with_position(
-                    "expected '=>' after key",
-                    self.position,
-                ));
+            self.add_element(entries.len() + 1)?;
+            let start = self.position;
+            let entry_start = start;
+            match self.parse_dict_key() {
+                Ok(key) => {
+                    let value = self.parse_key_separator().and_then(|()| self.parse_value());
+                    let value = self.recover(value, Value::Void)?;
+                    let inserted = self.insert_entry(&mut entries, key.into_owned(), value, start);
+                    self.recover(inserted, ())?;
+                }
+                Err(err) => self.recover(Err(err), ())?,
             }
-            let value = self.parse_value()?;
-            entries.insert(key, value);
-            self.skip_ws()?;
-            if self.consume_ascii(',') {
-                continue;
-            } else if self.consume_ascii(']') {
+            if self.end_of_entry(entry_start)? {
                 break;
-            } else {
-                return Err(Error::with_position("expected ',' or ']'", self.position));
             }
         }
         Ok(Value::Dictionary(entries))
     }
 
-    fn parse_dict_key(&mut self) -> Result<String> {
+    /// Adds an entry whose key starts at `position`, resolving duplicate keys
+    /// according to the options.
+    fn insert_entry(
+        &mut self,
+        entries: &mut IndexMap<String, Value>,
+        key: String,
+        value: Value,
+        position: usize,
+    ) -> Result<()> {
+        let Some(index) = entries.get_index_of(&key) else {
+            entries.insert(key, value);
+            return Ok(());
+        };
+        let err = self.error_at(
+            ErrorKind::DuplicateKey,
+            format!("duplicate key {key:?}"),
+            position,
+        );
+        match self.limits.duplicate_keys {
+            DuplicateKeys::Error => return Err(err),
+            DuplicateKeys::FirstWins => {}
+            DuplicateKeys::LastWins => entries[index] = value,
+            DuplicateKeys::LastWinsMoveToEnd => {
+                entries.shift_remove_index(index);
+                entries.insert(key, value);
+            }
+        }
+        self.duplicates.push(err);
+        Ok(())
+    }
+
+    fn parse_key_separator(&mut self) -> Result<()> {
+        self.skip_ws()?;
+        if self.consume_exact("=>") {
+            // ok
+        } else if self.consume_ascii(':') {
+            // legacy form
+        } else {
+            return Err(self.error(ErrorKind::Syntax, "expected '=>' after key"));
+        }
+        Ok(())
+    }
+
+    /// Moves past the separator after an array element or dictionary entry
+    /// that started at `entry_start`, returning `true` once the container has
+    /// been closed.
+    fn end_of_entry(&mut self, entry_start: usize) -> Result<bool> {
+        let skipped = self.skip_ws();
+        self.recover(skipped, ())?;
+        if self.consume_ascii(',') {
+            return Ok(false);
+        } else if self.consume_ascii(']') {
+            return Ok(true);
+        }
+        let err = self.error(ErrorKind::Syntax, "expected ',' or ']'");
+        self.recover(Err(err), ())?;
+        if self.position == entry_start && !matches!(self.peek_byte(), None | Some(b',' | b']')) {
+            // Nothing was read since the entry started, as when a dictionary
+            // stands where a key is expected. Skip it as a whole, or at least
+            // one character, so that every entry moves the parser forward.
+            let skipped = self.parse_value();
+            self.recover(skipped, Value::Void)?;
+            if self.position == entry_start {
+                self.next_char();
+            }
+            let skipped = self.skip_ws();
+            self.recover(skipped, ())?;
+        }
+        // Resynchronized at a separator, a dictionary or the end of input.
+        if self.consume_ascii(',') {
+            Ok(false)
+        } else {
+            Ok(self.consume_ascii(']') || self.is_eof())
+        }
+    }
+
+    /// In recovery mode, records the error in `result` and skips to the next
+    /// point where parsing can resume, returning `placeholder` instead.
+    fn recover<T>(&mut self, result: Result<T>, placeholder: T) -> Result<T> {
+        let err = match result {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        let Some(errors) = &mut self.errors else {
+            return Err(err);
+        };
+        // A single mistake often surfaces again in every enclosing container.
+        if errors
+            .last()
+            .is_none_or(|last| last.position != err.position)
+        {
+            errors.push(err);
+        }
+        self.synchronize();
+        Ok(placeholder)
+    }
+
+    /// Skips a string literal without decoding it.
+    fn skip_quoted(&mut self, quote: u8) {
+        self.position += 1;
+        while let Some(byte) = self.next_byte() {
+            if byte == quote {
+                return;
+            }
+            if byte == b'\\' {
+                self.next_char();
+            }
+        }
+    }
+
+    /// Skips input up to the next `,` or `]` of the current container, or
+    /// to the start of a dictionary, whichever comes first.
+    fn synchronize(&mut self) {
+        let mut depth = 0usize;
+        while let Some(byte) = self.peek_byte() {
+            match byte {
+                b',' | b']' if depth == 0 => return,
+                b'%' if depth == 0
+                    && self.input[self.position + 1..]
+                        .trim_start()
+                        .starts_with('[') =>
+                {
+                    return;
+                }
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                b'"' | b'\'' => {
+                    self.skip_quoted(byte);
+                    continue;
+                }
+                b'/' if self.starts_with("//") || self.starts_with("/*") => {
+                    if self.skip_ws().is_err() {
+                        self.position = self.bytes.len();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            self.position += 1;
+        }
+    }
+
+    pub(crate) fn parse_dict_key(&mut self) -> Result<Cow<'a, str>> {
         match self.peek_byte() {
             Some(b'"') | Some(b'\'') => self.parse_string(),
             _ => self
                 .parse_identifier()
-                .ok_or_else(|| Error::with_position("expected dictionary key", self.position)),
+                .map(Cow::Borrowed)
+                .ok_or_else(|| self.error(ErrorKind::Syntax, "expected dictionary key")),
         }
     }
 
-    fn parse_literal(&mut self) -> Result<Value> {
+    fn skip_dict_key(&mut self) -> Result<()> {
+        match self.peek_byte() {
+            Some(b'"') | Some(b'\'') => self.skip_string(),
+            _ => self
+                .parse_identifier()
+                .map(drop)
+                .ok_or_else(|| self.error(ErrorKind::Syntax, "expected dictionary key")),
+        }
+    }
+
+    pub(crate) fn parse_literal(&mut self) -> Result<Value> {
         if self.consume_exact("true") {
             Ok(Value::Bool(true))
         } else if self.consume_exact("false") {
@@ -173,11 +648,11 @@ impl<'a> Parser<'a> {
         } else if self.consume_exact("Infinity") {
             Ok(Value::Real(f64::INFINITY))
         } else {
-            Err(Error::with_position("unknown literal", self.position))
+            Err(self.error(ErrorKind::Syntax, "unknown literal"))
         }
     }
 
-    fn parse_number(&mut self) -> Result<Value> {
+    pub(crate) fn parse_number(&mut self) -> Result<Value> {
         if self.starts_with("-Infinity") {
             self.position += "-Infinity".len();
             return Ok(Value::Real(f64::NEG_INFINITY));
@@ -201,19 +676,16 @@ impl<'a> Parser<'a> {
 
         if self.starts_with("0x") || self.starts_with("0X") {
             self.position += 2;
-            let digits_start = self.position;
-            self.consume_digits(|b| b.is_ascii_hexdigit());
-            if self.position == digits_start {
-                return Err(Error::with_position("expected hex digits", self.position));
-            }
-            let digits = &self.input[digits_start..self.position];
-            let unsigned = i128::from_str_radix(digits, 16)
-                .map_err(|_| Error::with_position("invalid hex number", digits_start))?;
-            let signed = if negative { -unsigned } else { unsigned };
-            if signed < i128::from(i64::MIN) || signed > i128::from(i64::MAX) {
-                return Err(Error::with_position("integer overflow", start));
-            }
-            return Ok(Value::Integer(signed as i64));
+            return self.parse_radix_number(start, negative, 4, "hex");
+        }
+        if self.starts_with("0b") || self.starts_with("0B") {
+            self.position += 2;
+            return self.parse_radix_number(start, negative, 1, "binary");
+        }
+        if self.peek_byte() == Some(b'0')
+            && matches!(self.bytes.get(self.position + 1), Some(b'0'..=b'9'))
+        {
+            return self.parse_radix_number(start, negative, 3, "octal");
         }
 
         let mut seen_digit = false;
@@ -255,22 +727,19 @@ impl<'a> Parser<'a> {
                 }
             }
             if exp_digits == 0 {
-                return Err(Error::with_position(
-                    "expected exponent digits",
-                    self.position,
-                ));
+                return Err(self.error(ErrorKind::Syntax, "expected exponent digits"));
             }
         }
 
         if !seen_digit {
-            return Err(Error::with_position("expected number", start));
+            return Err(self.error_at(ErrorKind::Syntax, "expected number", start));
         }
 
         let slice = &self.input[start..self.position];
         if is_float {
             let value = slice
                 .parse::<f64>()
-                .map_err(|_| Error::with_position("invalid number", start))?;
+                .map_err(|_| self.error_at(ErrorKind::Syntax, "invalid number", start))?;
             Ok(Value::Real(value))
         } else {
             match slice.parse::<i64>() {
@@ -278,21 +747,213 @@ impl<'a> Parser<'a> {
                 Err(_) => slice
                     .parse::<f64>()
                     .map(Value::Real)
-                    .map_err(|_| Error::with_position("invalid number", start)),
+                    .map_err(|_| self.error_at(ErrorKind::Syntax, "invalid number", start)),
+            }
+        }
+    }
+
+    /// Parses a binary, octal or hexadecimal literal after its prefix, with
+    /// `bits` bits per digit.
+    ///
+    /// A literal with a fraction or a binary exponent (as in `0x1.8p+3`) is a
+    /// real number; anything else has to fit in an `i64`.
+    fn parse_radix_number(
+        &mut self,
+        start: usize,
+        negative: bool,
+        bits: u32,
+        name: &str,
+    ) -> Result<Value> {
+        let radix = 1 << bits;
+        let digit = |byte: u8| hex_value(byte).filter(|&digit| digit < radix);
+        // The leading 64 bits of the digits, scaled by `2^exponent`. Any
+        // nonzero bits beyond those are recorded in `sticky`.
+        let mut mantissa = 0u64;
+        let mut exponent = 0i64;
+        let mut sticky = false;
+        let mut seen_digit = false;
+        let mut is_real = false;
+        let mut fraction = false;
+        loop {
+            match self.peek_byte().and_then(digit) {
+                Some(value) => {
+                    let value = u64::from(value);
+                    seen_digit = true;
+                    if mantissa >> (64 - bits) == 0 {
+                        mantissa = (mantissa << bits) | value;
+                        if fraction {
+                            exponent -= i64::from(bits);
+                        }
+                    } else {
+                        sticky |= value != 0;
+                        if !fraction {
+                            exponent += i64::from(bits);
+                        }
+                    }
+                }
+                None if !fraction && self.peek_byte() == Some(b'.') => {
+                    fraction = true;
+                    is_real = true;
+                }
+                None => break,
             }
+            self.position += 1;
+        }
+        if !seen_digit {
+            return Err(self.error(ErrorKind::Syntax, format!("expected {name} digits")));
+        }
+        if matches!(self.peek_byte(), Some(b'p' | b'P')) {
+            self.position += 1;
+            is_real = true;
+            exponent = exponent.saturating_add(self.parse_binary_exponent()?);
+        }
+        if self
+            .peek_byte()
+            .is_some_and(|byte| byte.is_ascii_alphanumeric())
+        {
+            return Err(self.error(ErrorKind::Syntax, format!("invalid digit in {name} number")));
+        }
+
+        if is_real {
+            let value = compose_real(mantissa, sticky, exponent);
+            return Ok(Value::Real(if negative { -value } else { value }));
         }
+        let limit = if negative {
+            i64::MIN.unsigned_abs()
+        } else {
+            i64::MAX.unsigned_abs()
+        };
+        if exponent > 0 || mantissa > limit {
+            return Err(self.error_at(ErrorKind::Overflow, "integer overflow", start));
+        }
+        Ok(Value::Integer(if negative {
+            0i64.wrapping_sub_unsigned(mantissa)
+        } else {
+            mantissa as i64
+        }))
     }
 
-    fn parse_string(&mut self) -> Result<String> {
+    /// Parses the signed decimal exponent after the `p` of a real number
+    /// written in another radix.
+    fn parse_binary_exponent(&mut self) -> Result<i64> {
+        let negative = self.consume_ascii('-');
+        if !negative {
+            self.consume_ascii('+');
+        }
+        let digits_start = self.position;
+        self.consume_digits(|byte| byte.is_ascii_digit());
+        if self.position == digits_start {
+            return Err(self.error(ErrorKind::Syntax, "expected exponent digits"));
+        }
+        // Anything this large is zero or infinite either way.
+        let exponent = self.input[digits_start..self.position]
+            .parse::<i64>()
+            .unwrap_or(i64::MAX)
+            .min(1 << 20);
+        Ok(if negative { -exponent } else { exponent })
+    }
+
+    /// Parses a quoted string, borrowing it from the input when it contains
+    /// no escape sequences.
+    ///
+    /// Adjacent literals, optionally joined by `+` (as in `"a" "b"` or
+    /// `"a" + 'b'`), are concatenated into one string.
+    pub(crate) fn parse_string(&mut self) -> Result<Cow<'a, str>> {
+        self.string_literal(Self::read_string, |text, next| {
+            text.to_mut().push_str(&next)
+        })
+    }
+
+    /// Reads a string like [`Parser::parse_string`] without decoding it.
+    fn skip_string(&mut self) -> Result<()> {
+        self.string_literal(Self::measure_string, |len, next| *len += next)
+            .map(drop)
+    }
+
+    /// Reads adjacent string literals with `read`, joining them with `join`
+    /// and checking the length of the result.
+    fn string_literal<T, R, J>(&mut self, mut read: R, mut join: J) -> Result<T>
+    where
+        T: StringLen,
+        R: FnMut(&mut Self) -> Result<T>,
+        J: FnMut(&mut T, T),
+    {
+        let start = self.position;
+        let mut text = read(self)?;
+        loop {
+            let end = self.position;
+            self.skip_ws()?;
+            let joined = self.consume_ascii('+');
+            if joined {
+                self.skip_ws()?;
+            }
+            if matches!(self.peek_byte(), Some(b'"' | b'\'')) {
+                let next = read(self)?;
+                join(&mut text, next);
+            } else if joined {
+                return Err(self.error(ErrorKind::Syntax, "expected string after '+'"));
+            } else {
+                self.position = end;
+                break;
+            }
+        }
+        let len = text.len();
+        if exceeds(len, self.limits.max_string_len) {
+            return Err(self.error_at(
+                ErrorKind::StringLimitExceeded,
+                format!("string of {len} bytes exceeds the length limit"),
+                start,
+            ));
+        }
+        Ok(text)
+    }
+
+    /// Reads a quoted string like [`Parser::read_string`], returning only the
+    /// length of its text in bytes.
+    fn measure_string(&mut self) -> Result<usize> {
+        let quote = self
+            .next_byte()
+            .ok_or_else(|| self.error(ErrorKind::Eof, "unexpected end of input"))?
+            as char;
+        let mut len = 0;
+        loop {
+            let ch = self
+                .next_char()
+                .ok_or_else(|| self.error(ErrorKind::Eof, "unterminated string"))?;
+            if ch == quote {
+                return Ok(len);
+            }
+            len += if ch == '\\' {
+                self.parse_escape()?.len_utf8()
+            } else {
+                ch.len_utf8()
+            };
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Cow<'a, str>> {
         let quote = self
             .next_byte()
-            .ok_or_else(|| Error::with_position("unexpected end of input", self.position))?
+            .ok_or_else(|| self.error(ErrorKind::Eof, "unexpected end of input"))?
             as char;
-        let mut output = String::new();
+        let start = self.position;
         loop {
             let ch = self
                 .next_char()
-                .ok_or_else(|| Error::with_position("unterminated string", self.position))?;
+                .ok_or_else(|| self.error(ErrorKind::Eof, "unterminated string"))?;
+            if ch == quote {
+                return Ok(Cow::Borrowed(&self.input[start..self.position - 1]));
+            }
+            if ch == '\\' {
+                self.position -= 1;
+                break;
+            }
+        }
+        let mut output = self.input[start..self.position].to_string();
+        loop {
+            let ch = self
+                .next_char()
+                .ok_or_else(|| self.error(ErrorKind::Eof, "unterminated string"))?;
             if ch == quote {
                 break;
             }
@@ -302,90 +963,137 @@ impl<'a> Parser<'a> {
                 output.push(ch);
             }
         }
-        Ok(output)
+        Ok(Cow::Owned(output))
     }
 
+    /// Parses an escape sequence after its backslash, joining a pair of
+    /// escaped UTF-16 surrogates into one character.
     fn parse_escape(&mut self) -> Result<char> {
+        let start = self.position - 1;
+        let mut code = self.parse_escape_code()?;
+        if (0xD800..0xDC00).contains(&code) && self.consume_ascii('\\') {
+            let low = self.parse_escape_code()?;
+            if (0xDC00..0xE000).contains(&low) {
+                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+            }
+        }
+        char::from_u32(code).ok_or_else(|| {
+            self.error_at(
+                ErrorKind::InvalidEscape,
+                "unpaired surrogate in escape sequence",
+                start,
+            )
+        })
+    }
+
+    fn parse_escape_code(&mut self) -> Result<u32> {
+        let start = self.position - 1;
         let ch = self
             .next_char()
-            .ok_or_else(|| Error::with_position("unterminated escape", self.position))?;
+            .ok_or_else(|| self.error(ErrorKind::Eof, "unterminated escape"))?;
         Ok(match ch {
-            'n' => '\n',
-            'r' => '\r',
-            't' => '\t',
-            'b' => '\x08',
-            'f' => '\x0c',
-            '\\' => '\\',
-            '\'' => '\'',
-            '"' => '"',
-            '0' => '\0',
-            'x' => {
-                let value = self.read_hex_digits(2)?;
-                char::from_u32(value as u32)
-                    .ok_or_else(|| Error::with_position("invalid hex escape", self.position))?
-            }
-            'u' => {
-                let value = self.read_hex_digits(4)?;
-                char::from_u32(value as u32)
-                    .ok_or_else(|| Error::with_position("invalid unicode escape", self.position))?
-            }
-            other => other,
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'n' => 0x0a,
+            'r' => 0x0d,
+            't' => 0x09,
+            'v' => 0x0b,
+            'x' | 'X' => self.read_hex_digits(1, 4)?,
+            'u' => self.read_hex_digits(4, 4)?,
+            '0'..='7' => {
+                let mut value = ch as u32 - '0' as u32;
+                for _ in 0..2 {
+                    match self.peek_byte() {
+                        Some(digit @ b'0'..=b'7') => {
+                            self.position += 1;
+                            value = (value << 3) | u32::from(digit - b'0');
+                        }
+                        _ => break,
+                    }
+                }
+                value
+            }
+            '\\' | '\'' | '"' => ch as u32,
+            _ if self.limits.strict_escapes => {
+                return Err(self.error_at(
+                    ErrorKind::InvalidEscape,
+                    format!("unknown escape sequence '\\{ch}'"),
+                    start,
+                ));
+            }
+            other => other as u32,
         })
     }
 
-    fn read_hex_digits(&mut self, count: usize) -> Result<u32> {
+    /// Reads between `min` and `max` hex digits.
+    fn read_hex_digits(&mut self, min: usize, max: usize) -> Result<u32> {
         let mut value = 0u32;
-        for _ in 0..count {
-            let digit = self
-                .peek_byte()
-                .ok_or_else(|| Error::with_position("unexpected end of input", self.position))?;
-            let parsed = hex_value(digit)
-                .ok_or_else(|| Error::with_position("invalid hex digit", self.position))?;
+        for count in 0..max {
+            let Some(digit) = self.peek_byte().and_then(hex_value) else {
+                if count >= min {
+                    break;
+                } else if self.is_eof() {
+                    return Err(self.error(ErrorKind::Eof, "unexpected end of input"));
+                }
+                return Err(self.error(ErrorKind::InvalidEscape, "invalid hex digit"));
+            };
             self.position += 1;
-            value = (value << 4) | parsed as u32;
+            value = (value << 4) | u32::from(digit);
         }
         Ok(value)
     }
 
-    fn parse_octet(&mut self) -> Result<Value> {
-        self.expect_str("<%")?;
+    pub(crate) fn parse_octet(&mut self) -> Result<Value> {
         let mut bytes = Vec::new();
+        self.read_octet(|byte| bytes.push(byte))?;
+        Ok(Value::Octet(bytes))
+    }
+
+    /// Reads an octet literal, passing each of its bytes to `push`.
+    fn read_octet<F: FnMut(u8)>(&mut self, mut push: F) -> Result<()> {
+        self.expect_str("<%")?;
+        let mut len = 0;
         loop {
             self.skip_inline_ws();
             if self.starts_with("%>") {
                 self.position += 2;
-                break;
+                return Ok(());
+            }
+            len += 1;
+            if exceeds(len, self.limits.max_octet_len) {
+                return Err(self.error(
+                    ErrorKind::OctetLimitExceeded,
+                    "octet exceeds the length limit",
+                ));
             }
             let high = self.read_octet_digit()?;
             let low = self.read_octet_digit()?;
-            bytes.push((high << 4) | low);
+            push((high << 4) | low);
         }
-        Ok(Value::Octet(bytes))
     }
 
     fn read_octet_digit(&mut self) -> Result<u8> {
         let digit = self
             .peek_byte()
-            .ok_or_else(|| Error::with_position("unexpected end of input", self.position))?;
+            .ok_or_else(|| self.error(ErrorKind::Eof, "unexpected end of input"))?;
         if let Some(value) = hex_value(digit) {
             self.position += 1;
             Ok(value)
         } else {
-            Err(Error::with_position("invalid octet digit", self.position))
+            Err(self.error(ErrorKind::Syntax, "invalid octet digit"))
         }
     }
 
-    fn parse_identifier(&mut self) -> Option<String> {
+    fn parse_identifier(&mut self) -> Option<&'a str> {
         let start = self.position;
-        let Some(first) = self.peek_byte() else {
-            return None;
-        };
+        let first = self.peek_byte()?;
         if !is_ident_start(first) {
             return None;
         }
         self.position += 1;
         self.consume_digits(is_ident_continue);
-        Some(self.input[start..self.position].to_string())
+        Some(&self.input[start..self.position])
     }
 
     fn consume_digits<F: Fn(u8) -> bool>(&mut self, predicate: F) {
@@ -433,7 +1141,7 @@ impl<'a> Parser<'a> {
         self.input[self.position..].starts_with(token)
     }
 
-    fn consume_exact(&mut self, token: &str) -> bool {
+    pub(crate) fn consume_exact(&mut self, token: &str) -> bool {
         if self.starts_with(token) {
             self.position += token.len();
             true
@@ -442,16 +1150,13 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn expect_char(&mut self, ch: char) -> Result<()> {
+    pub(crate) fn expect_char(&mut self, ch: char) -> Result<()> {
         match self.peek_byte() {
             Some(byte) if byte == ch as u8 => {
                 self.position += 1;
                 Ok(())
             }
-            _ => Err(Error::with_position(
-                format!("expected '{ch}'"),
-                self.position,
-            )),
+            _ => Err(self.error(ErrorKind::Syntax, format!("expected '{ch}'"))),
         }
     }
 
@@ -459,14 +1164,11 @@ impl<'a> Parser<'a> {
         if self.consume_exact(token) {
             Ok(())
         } else {
-            Err(Error::with_position(
-                format!("expected '{token}'"),
-                self.position,
-            ))
+            Err(self.error(ErrorKind::Syntax, format!("expected '{token}'")))
         }
     }
 
-    fn consume_ascii(&mut self, ch: char) -> bool {
+    pub(crate) fn consume_ascii(&mut self, ch: char) -> bool {
         if self.peek_byte() == Some(ch as u8) {
             self.position += 1;
             true
@@ -475,7 +1177,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn peek_byte(&self) -> Option<u8> {
+    pub(crate) fn peek_byte(&self) -> Option<u8> {
         self.bytes.get(self.position).copied()
     }
 
@@ -500,6 +1202,69 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Computes `mantissa * 2^exponent` rounded to the nearest `f64`, where
+/// `sticky` tells whether nonzero bits below the mantissa were dropped.
+///
+/// The value is rounded once, to 53 bits for normal results and to fewer
+/// for subnormal ones, so that it never suffers from double rounding.
+fn compose_real(mantissa: u64, sticky: bool, exponent: i64) -> f64 {
+    if mantissa == 0 {
+        return 0.0;
+    }
+    let shift = mantissa.leading_zeros();
+    let mantissa = mantissa << shift;
+    // The value lies in `[2^top, 2^(top + 1))`.
+    let top = exponent.saturating_sub(i64::from(shift)).saturating_add(63);
+    if top > 1023 {
+        return f64::INFINITY;
+    }
+    // Subnormal results keep only the bits down to 2^-1074.
+    let precision = if top >= -1022 { 53 } else { top + 1075 };
+    if precision < 0 {
+        // Below half of the smallest subnormal.
+        return 0.0;
+    }
+    let dropped = 64 - precision as u32;
+    let wide = u128::from(mantissa);
+    let mut kept = (wide >> dropped) as u64;
+    let rest = wide & ((1 << dropped) - 1);
+    let half = 1 << (dropped - 1);
+    if rest > half || (rest == half && (sticky || kept & 1 == 1)) {
+        kept += 1;
+    }
+    if top < -1022 {
+        // Rounding up to 2^52 yields the smallest normal number, whose bits
+        // continue those of the subnormals.
+        return f64::from_bits(kept);
+    }
+    let (kept, top) = if kept == 1 << 53 {
+        (kept >> 1, top + 1)
+    } else {
+        (kept, top)
+    };
+    if top > 1023 {
+        return f64::INFINITY;
+    }
+    f64::from_bits(((top + 1023) as u64) << 52 | (kept & ((1 << 52) - 1)))
+}
+
+/// The length in bytes of a string read by [`Parser::string_literal`].
+trait StringLen {
+    fn len(&self) -> usize;
+}
+
+impl StringLen for Cow<'_, str> {
+    fn len(&self) -> usize {
+        str::len(self)
+    }
+}
+
+impl StringLen for usize {
+    fn len(&self) -> usize {
+        *self
+    }
+}
+
 fn hex_value(byte: u8) -> Option<u8> {
     match byte {
         b'0'..=b'9' => Some(byte - b'0'),
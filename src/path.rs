@@ -0,0 +1,116 @@
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::parser::Parser;
+use crate::value::{Value, write_string};
+
+/// One step from a container to one of its children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    /// An element of an array.
+    Index(usize),
+    /// An entry of a dictionary.
+    Key(String),
+}
+
+/// A location inside a TJS value, displayed as `phones[1].number`.
+///
+/// Keys that are not plain identifiers are displayed in brackets, as in
+/// `table["two words"]`. The same syntax is accepted by [`str::parse`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Path {
+    segments: Vec<PathSegment>,
+}
+
+impl Path {
+    /// Returns the segments of this path, outermost first.
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    /// Returns `true` if this path refers to the root value.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub(crate) fn push_front(&mut self, segment: PathSegment) {
+        self.segments.insert(0, segment);
+    }
+}
+
+impl FromIterator<PathSegment> for Path {
+    fn from_iter<I: IntoIterator<Item = PathSegment>>(iter: I) -> Self {
+        Self {
+            segments: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl FromStr for Path {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut parser = Parser::new(text);
+        let mut segments = Vec::new();
+        while !parser.is_eof() {
+            if parser.consume_ascii('[') {
+                let segment = if matches!(parser.peek_byte(), Some(b'"' | b'\'')) {
+                    PathSegment::Key(parser.parse_string()?.into_owned())
+                } else {
+                    let start = parser.position();
+                    match parser.parse_number() {
+                        Ok(Value::Integer(index)) if index >= 0 => {
+                            PathSegment::Index(index as usize)
+                        }
+                        _ => {
+                            return Err(parser.error_at(
+                                ErrorKind::Syntax,
+                                "expected an array index or a quoted key",
+                                start,
+                            ));
+                        }
+                    }
+                };
+                parser.expect_char(']')?;
+                segments.push(segment);
+            } else {
+                if !segments.is_empty() {
+                    parser.expect_char('.')?;
+                }
+                segments.push(PathSegment::Key(parser.parse_dict_key()?.into_owned()));
+            }
+        }
+        Ok(Self { segments })
+    }
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch == '_' || ch.is_ascii_alphabetic())
+        && chars.all(|ch| ch == '_' || ch.is_ascii_alphanumeric())
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, segment) in self.segments.iter().enumerate() {
+            match segment {
+                PathSegment::Index(index) => write!(f, "[{index}]")?,
+                PathSegment::Key(key) if is_identifier(key) => {
+                    if idx > 0 {
+                        f.write_char('.')?;
+                    }
+                    f.write_str(key)?;
+                }
+                PathSegment::Key(key) => {
+                    f.write_char('[')?;
+                    write_string(f, key)?;
+                    f.write_char(']')?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
-use std::io::Write as IoWrite;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write as IoWrite};
+use std::str;
 
 use indexmap::IndexMap;
 use serde::ser::{
@@ -6,10 +8,17 @@ use serde::ser::{
     SerializeTupleVariant,
 };
 
-use crate::error::{Error, Result};
-use crate::value::{SerializeOptions, Value};
+use crate::encoding::{EncodedWriter, Encoding};
+use crate::error::{Error, ErrorKind, Result};
+use crate::storage;
+use crate::value::{self, ColumnWriter, KeyOrder, SerializeOptions, Value};
 
 /// Convert a `T` into `serde_tjs::Value` which is an enum that can represent any valid TJS2 data.
+///
+/// An `f32` becomes the [`Value::Real`] of the same number. The `Value` no
+/// longer knows it was an `f32`, so it prints with the digits of an `f64`:
+/// `0.1f32` prints as `0.10000000149011612`, while [`to_string`] writes the
+/// shortest digits that read back as the same `f32`, `0.1`.
 pub fn to_value<T>(value: T) -> Result<Value>
 where
     T: Serialize,
@@ -39,8 +48,9 @@ pub fn to_string_with_options<T>(value: &T, options: &SerializeOptions) -> Resul
 where
     T: ?Sized + Serialize,
 {
-    let value = value.serialize(ValueSerializer)?;
-    Ok(value.to_string_with_options(options))
+    let mut serializer = Serializer::with_options(Vec::new(), options);
+    value.serialize(&mut serializer)?;
+    String::from_utf8(serializer.into_inner()).map_err(|err| Error::utf8(err.utf8_error()))
 }
 
 /// Serialize the given data structure as a `Vec<u8>` of TJS2 text.
@@ -90,20 +100,36 @@ where
 }
 
 /// Serialize the given data structure with custom options by writing TJS2 text into the provided writer.
-pub fn to_writer_with_options<W, T>(
-    mut writer: W,
-    value: &T,
-    options: &SerializeOptions,
-) -> Result<()>
+///
+/// The text is encoded as requested by `options.encoding`, `options.write_bom`
+/// and `options.storage`.
+pub fn to_writer_with_options<W, T>(writer: W, value: &T, options: &SerializeOptions) -> Result<()>
 where
     W: IoWrite,
     T: ?Sized + Serialize,
 {
-    let value = value.serialize(ValueSerializer)?;
-    let output = value.to_string_with_options(options);
-    writer
-        .write_all(output.as_bytes())
-        .map_err(|err| Error::new(err.to_string()))
+    storage::write_text(writer, options, |output| {
+        let mut serializer = Serializer::with_options(TextSink(output), options);
+        value.serialize(&mut serializer)
+    })
+}
+
+/// Forwards the UTF-8 text written by a [`Serializer`] to a [`fmt::Write`].
+struct TextSink<'a>(&'a mut dyn fmt::Write);
+
+impl io::Write for TextSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text =
+            str::from_utf8(buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.0
+            .write_str(text)
+            .map_err(|_| io::Error::other("failed to write TJS2 text"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 fn pretty_options() -> SerializeOptions {
@@ -113,6 +139,506 @@ fn pretty_options() -> SerializeOptions {
     }
 }
 
+/// A serializer that writes TJS2 text straight to an [`io::Write`], without
+/// building an intermediate [`Value`].
+///
+/// The text is written as UTF-8. The `encoding`, `write_bom` and `storage`
+/// options are applied by [`to_writer_with_options`], which uses this
+/// serializer. Dictionary keys are written as they come, so a key that is
+/// serialized twice also appears twice in the output, unless
+/// [`SerializeOptions::key_order`], [`SerializeOptions::align_arrows`] or
+/// [`SerializeOptions::max_width`] collects the dictionary first.
+pub struct Serializer<W> {
+    output: EncodedWriter<W>,
+    options: SerializeOptions,
+    /// Number of containers currently open.
+    depth: usize,
+    /// Column of the line being written.
+    column: usize,
+}
+
+impl<W: IoWrite> Serializer<W> {
+    /// Creates a serializer writing compact TJS2 text.
+    pub fn new(writer: W) -> Self {
+        Self::with_options(writer, &SerializeOptions::default())
+    }
+
+    /// Creates a serializer writing pretty-printed TJS2 text.
+    pub fn pretty(writer: W) -> Self {
+        Self::with_options(writer, &pretty_options())
+    }
+
+    /// Creates a serializer writing TJS2 text with custom options.
+    pub fn with_options(writer: W, options: &SerializeOptions) -> Self {
+        Self {
+            output: EncodedWriter::new(writer, Encoding::Utf8),
+            options: options.clone(),
+            depth: 0,
+            column: 0,
+        }
+    }
+
+    /// Unwraps the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.output.into_inner()
+    }
+
+    fn write<F>(&mut self, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut ColumnWriter<&mut EncodedWriter<W>>, &SerializeOptions) -> fmt::Result,
+    {
+        let mut output = ColumnWriter::new(&mut self.output, self.column);
+        let result = write(&mut output, &self.options);
+        self.column = output.column;
+        result.map_err(|_| self.output.take_error())
+    }
+
+    /// Writes a container that was collected into a [`Value`].
+    fn write_value(&mut self, value: &Value) -> Result<()> {
+        let (depth, column) = (self.depth, self.column);
+        self.write(|output, options| value.write_nested(output, options, depth, column))
+    }
+
+    /// Returns `true` if a container has to be collected before it can be
+    /// written. Dictionaries are also collected to sort their keys.
+    fn buffers(&self, dictionary: bool) -> bool {
+        value::needs_lookahead(&self.options)
+            || dictionary && self.options.key_order != KeyOrder::Preserve
+    }
+
+    fn open<B>(&mut self, dictionary: bool) -> Result<Compound<'_, W, B>> {
+        Ok(Compound::Stream(self.open_stream(dictionary)?))
+    }
+
+    fn open_stream(&mut self, dictionary: bool) -> Result<Stream<'_, W>> {
+        self.write(|output, options| value::write_open(output, options, dictionary))?;
+        self.depth += 1;
+        Ok(Stream {
+            ser: self,
+            first: true,
+            variant: false,
+        })
+    }
+
+    /// Opens the dictionary that holds the content of `variant`, and then the
+    /// container of that content.
+    fn open_variant<B>(&mut self, variant: &str, dictionary: bool) -> Result<Compound<'_, W, B>> {
+        self.open_stream(true)?.entry(Some(variant))?;
+        let mut stream = self.open_stream(dictionary)?;
+        stream.variant = true;
+        Ok(Compound::Stream(stream))
+    }
+
+    fn buffer<B>(&mut self, buffer: B) -> Result<Compound<'_, W, B>> {
+        Ok(Compound::Buffered { ser: self, buffer })
+    }
+}
+
+impl<'a, W: IoWrite> serde::Serializer for &'a mut Serializer<W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a, W, SeqSerializer>;
+    type SerializeTuple = Compound<'a, W, SeqSerializer>;
+    type SerializeTupleStruct = Compound<'a, W, SeqSerializer>;
+    type SerializeTupleVariant = Compound<'a, W, TupleVariantSerializer>;
+    type SerializeMap = Compound<'a, W, MapSerializer>;
+    type SerializeStruct = Compound<'a, W, MapSerializer>;
+    type SerializeStructVariant = Compound<'a, W, StructVariantSerializer>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.write(|output, _| output.write_str(if v { "true" } else { "false" }))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.write(|output, _| write!(output, "{}", v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        if v <= i64::MAX as u64 {
+            self.serialize_i64(v as i64)
+        } else {
+            self.serialize_f64(v as f64)
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.write(|output, options| value::write_f32(output, v, options.float_style))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.write(|output, options| value::write_real(output, v, options.float_style))
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.write(|output, options| value::write_quoted(output, v, options.quote))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        let depth = self.depth;
+        self.write(|output, options| value::write_octet(output, v, options, depth))
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.write(|output, _| output.write_str("void"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.buffers(false) {
+            let value =
+                ValueSerializer.serialize_newtype_variant(name, variant_index, variant, value)?;
+            return self.write_value(&value);
+        }
+        let mut stream = self.open_stream(true)?;
+        stream.entry(Some(variant))?;
+        value.serialize(&mut *stream.ser)?;
+        stream.end()
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'a, W, SeqSerializer>> {
+        if self.buffers(false) {
+            let buffer = ValueSerializer.serialize_seq(len)?;
+            return self.buffer(buffer);
+        }
+        self.open(false)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a, W, SeqSerializer>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a, W, SeqSerializer>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a, W, TupleVariantSerializer>> {
+        if self.buffers(false) {
+            let buffer =
+                ValueSerializer.serialize_tuple_variant(name, variant_index, variant, len)?;
+            return self.buffer(buffer);
+        }
+        self.open_variant(variant, false)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'a, W, MapSerializer>> {
+        if self.buffers(true) {
+            let buffer = ValueSerializer.serialize_map(len)?;
+            return self.buffer(buffer);
+        }
+        self.open(true)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a, W, MapSerializer>> {
+        self.serialize_map(None)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a, W, StructVariantSerializer>> {
+        if self.buffers(true) {
+            let buffer =
+                ValueSerializer.serialize_struct_variant(name, variant_index, variant, len)?;
+            return self.buffer(buffer);
+        }
+        self.open_variant(variant, true)
+    }
+}
+
+/// An array or dictionary being written by a [`Serializer`].
+///
+/// When the layout of the container depends on all of its content (see
+/// [`SerializeOptions::align_arrows`] and [`SerializeOptions::max_width`]),
+/// or the keys of a dictionary are sorted (see
+/// [`SerializeOptions::key_order`]), the content is collected by `B` and
+/// written at the end.
+pub enum Compound<'a, W, B> {
+    Stream(Stream<'a, W>),
+    Buffered {
+        ser: &'a mut Serializer<W>,
+        buffer: B,
+    },
+}
+
+impl<W: IoWrite, B> Compound<'_, W, B> {
+    fn end(self, finish: impl FnOnce(B) -> Result<Value>) -> Result<()> {
+        match self {
+            Compound::Stream(stream) => stream.end(),
+            Compound::Buffered { ser, buffer } => ser.write_value(&finish(buffer)?),
+        }
+    }
+}
+
+/// An array or dictionary whose elements are written as they come.
+pub struct Stream<'a, W> {
+    ser: &'a mut Serializer<W>,
+    first: bool,
+    /// Whether the dictionary around an enum variant has to be closed too.
+    variant: bool,
+}
+
+impl<W: IoWrite> Stream<'_, W> {
+    /// Starts the next element, writing its key when in a dictionary.
+    fn entry(&mut self, key: Option<&str>) -> Result<()> {
+        let (first, depth) = (self.first, self.ser.depth);
+        self.first = false;
+        self.ser.write(|output, options| {
+            value::write_separator(output, options, first, depth)?;
+            match key {
+                Some(key) => value::write_key(output, options, key, 0),
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn element<T>(&mut self, key: Option<&str>, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.entry(key)?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        let empty = self.first;
+        let closing = if self.variant { 2 } else { 1 };
+        for level in 0..closing {
+            let depth = self.ser.depth;
+            self.ser.write(|output, options| {
+                value::write_close(output, options, empty && level == 0, depth)
+            })?;
+            self.ser.depth -= 1;
+        }
+        Ok(())
+    }
+}
+
+impl<W: IoWrite> SerializeSeq for Compound<'_, W, SeqSerializer> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self {
+            Compound::Stream(stream) => stream.element(None, value),
+            Compound::Buffered { buffer, .. } => buffer.serialize_element(value),
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self, SerializeSeq::end)
+    }
+}
+
+impl<W: IoWrite> serde::ser::SerializeTuple for Compound<'_, W, SeqSerializer> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl<W: IoWrite> serde::ser::SerializeTupleStruct for Compound<'_, W, SeqSerializer> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<()> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl<W: IoWrite> SerializeTupleVariant for Compound<'_, W, TupleVariantSerializer> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self {
+            Compound::Stream(stream) => stream.element(None, value),
+            Compound::Buffered { buffer, .. } => buffer.serialize_field(value),
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self, SerializeTupleVariant::end)
+    }
+}
+
+impl<W: IoWrite> SerializeMap for Compound<'_, W, MapSerializer> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self {
+            Compound::Stream(stream) => {
+                let key = key.serialize(MapKeySerializer)?;
+                stream.entry(Some(&key))
+            }
+            Compound::Buffered { buffer, .. } => buffer.serialize_key(key),
+        }
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self {
+            Compound::Stream(stream) => value.serialize(&mut *stream.ser),
+            Compound::Buffered { buffer, .. } => buffer.serialize_value(value),
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self, SerializeMap::end)
+    }
+}
+
+impl<W: IoWrite> SerializeStruct for Compound<'_, W, MapSerializer> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self {
+            Compound::Stream(stream) => stream.element(Some(key), value),
+            Compound::Buffered { buffer, .. } => {
+                SerializeStruct::serialize_field(buffer, key, value)
+            }
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self, SerializeStruct::end)
+    }
+}
+
+impl<W: IoWrite> SerializeStructVariant for Compound<'_, W, StructVariantSerializer> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self {
+            Compound::Stream(stream) => stream.element(Some(key), value),
+            Compound::Buffered { buffer, .. } => buffer.serialize_field(key, value),
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self, SerializeStructVariant::end)
+    }
+}
+
 pub struct ValueSerializer;
 
 impl serde::Serializer for ValueSerializer {
@@ -167,7 +693,7 @@ impl serde::Serializer for ValueSerializer {
     }
 
     fn serialize_f32(self, v: f32) -> Result<Value> {
-        Ok(Value::Real(v as f64))
+        Ok(Value::Real(f64::from(v)))
     }
 
     fn serialize_f64(self, v: f64) -> Result<Value> {
@@ -394,7 +920,7 @@ impl SerializeMap for MapSerializer {
         let key = self
             .next_key
             .take()
-            .ok_or_else(|| Error::new("value serialized before key"))?;
+            .ok_or_else(|| Error::new(ErrorKind::Custom, "value serialized before key"))?;
         let value = value.serialize(ValueSerializer)?;
         self.entries.insert(key, value);
         Ok(())
@@ -514,26 +1040,41 @@ impl serde::Serializer for MapKeySerializer {
     }
 
     fn serialize_bytes(self, _v: &[u8]) -> Result<String> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_none(self) -> Result<String> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_some<T>(self, _value: &T) -> Result<String>
     where
         T: ?Sized + Serialize,
     {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_unit(self) -> Result<String> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_unit_variant(
@@ -542,14 +1083,20 @@ impl serde::Serializer for MapKeySerializer {
         _variant_index: u32,
         _variant: &'static str,
     ) -> Result<String> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_newtype_struct<T>(self, _name: &'static str, _value: &T) -> Result<String>
     where
         T: ?Sized + Serialize,
     {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_newtype_variant<T>(
@@ -562,15 +1109,24 @@ impl serde::Serializer for MapKeySerializer {
     where
         T: ?Sized + Serialize,
     {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_tuple_struct(
@@ -578,7 +1134,10 @@ impl serde::Serializer for MapKeySerializer {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_tuple_variant(
@@ -588,15 +1147,24 @@ impl serde::Serializer for MapKeySerializer {
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn serialize_struct_variant(
@@ -606,7 +1174,10 @@ impl serde::Serializer for MapKeySerializer {
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        Err(Error::new("dictionary keys must be strings"))
+        Err(Error::new(
+            ErrorKind::TypeMismatch,
+            "dictionary keys must be strings",
+        ))
     }
 
     fn collect_str<T: ?Sized + std::fmt::Display>(self, value: &T) -> Result<String> {
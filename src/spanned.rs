@@ -0,0 +1,175 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Range;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+pub(crate) const NAME: &str = "$__serde_tjs_private_Spanned";
+pub(crate) const START: &str = "$__serde_tjs_private_start";
+pub(crate) const LINE: &str = "$__serde_tjs_private_line";
+pub(crate) const COLUMN: &str = "$__serde_tjs_private_column";
+pub(crate) const VALUE: &str = "$__serde_tjs_private_value";
+pub(crate) const END: &str = "$__serde_tjs_private_end";
+/// The fields in the order [`crate::Deserializer`] produces them.
+pub(crate) const FIELDS: &[&str] = &[START, LINE, COLUMN, VALUE, END];
+
+/// A value together with the location it was read from.
+///
+/// The span covers the value itself, without any `(const)` hint in front of
+/// it. Locations are only known when deserializing text, as with
+/// [`from_str`](crate::from_str); [`from_value`](crate::from_value) rejects
+/// `Spanned` fields.
+///
+/// Comparing and hashing only looks at the wrapped value.
+#[derive(Clone)]
+pub struct Spanned<T> {
+    span: Range<usize>,
+    line: usize,
+    column: usize,
+    value: T,
+}
+
+impl<T> Spanned<T> {
+    /// Returns the byte range of the value within the source text.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// Returns the byte offset where the value starts.
+    pub fn start(&self) -> usize {
+        self.span.start
+    }
+
+    /// Returns the byte offset just past the end of the value.
+    pub fn end(&self) -> usize {
+        self.span.end
+    }
+
+    /// Returns the 1-based line where the value starts.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the 1-based column where the value starts, counted in characters.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns a reference to the wrapped value.
+    pub fn get_ref(&self) -> &T {
+        &self.value
+    }
+
+    /// Returns a mutable reference to the wrapped value.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    /// Consumes the `Spanned`, returning the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} @ {:?} ", self.value, self.span)?;
+        write!(f, "(line {} column {})", self.line, self.column)
+    }
+}
+
+impl<T> Borrow<T> for Spanned<T> {
+    fn borrow(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> AsRef<T> for Spanned<T> {
+    fn as_ref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> AsMut<T> for Spanned<T> {
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Eq> Eq for Spanned<T> {}
+
+impl<T: Hash> Hash for Spanned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+    }
+}
+
+impl<T: PartialOrd> PartialOrd for Spanned<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl<T: Ord> Ord for Spanned<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.value.cmp(&other.value)
+    }
+}
+
+impl<T: Serialize> Serialize for Spanned<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Spanned<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(NAME, FIELDS, SpannedVisitor(PhantomData))
+    }
+}
+
+struct SpannedVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for SpannedVisitor<T> {
+    type Value = Spanned<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a spanned TJS value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut start = None;
+        let mut end = None;
+        let mut line = None;
+        let mut column = None;
+        let mut value = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                START => start = Some(map.next_value()?),
+                END => end = Some(map.next_value()?),
+                LINE => line = Some(map.next_value()?),
+                COLUMN => column = Some(map.next_value()?),
+                VALUE => value = Some(map.next_value()?),
+                _ => return Err(de::Error::custom("spanned value expected")),
+            }
+        }
+        match (start, end, line, column, value) {
+            (Some(start), Some(end), Some(line), Some(column), Some(value)) => Ok(Spanned {
+                span: start..end,
+                line,
+                column,
+                value,
+            }),
+            _ => Err(de::Error::custom("spanned value expected")),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::encoding::{self, EncodedWriter, Encoding};
+use crate::error::{Error, ErrorKind, Result};
+use crate::parser;
+use crate::value::SerializeOptions;
+
+/// How text is laid out in a byte stream.
+///
+/// Besides plain text, Kirikiri text streams may start with a
+/// `FE FE <mode> FF FE` header followed by a scrambled or compressed UTF-16LE
+/// body. Such input is recognized automatically by the byte-level entry points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Storage {
+    /// Plain text in the configured encoding.
+    #[default]
+    Plain,
+    /// UTF-16LE text scrambled with an XOR mask (mode 0).
+    XorScrambled,
+    /// UTF-16LE text with each pair of adjacent bits swapped (mode 1).
+    BitSwapped,
+    /// zlib-compressed UTF-16LE text with a length prefix (mode 2).
+    Compressed,
+}
+
+const HEADER_LEN: usize = 5;
+
+fn header(mode: u8) -> [u8; HEADER_LEN] {
+    [0xFE, 0xFE, mode, 0xFF, 0xFE]
+}
+
+fn xor_scramble(unit: u16) -> u16 {
+    if unit >= 0x20 {
+        unit ^ (((unit & 0xFE) << 8) ^ 1)
+    } else {
+        unit
+    }
+}
+
+fn swap_bits(unit: u16) -> u16 {
+    ((unit & 0xAAAA) >> 1) | ((unit & 0x5555) << 1)
+}
+
+/// Decodes `input` if it starts with a Kirikiri storage header.
+pub(crate) fn decode(input: &[u8], max_len: Option<usize>) -> Option<Result<String>> {
+    let [0xFE, 0xFE, mode, rest @ ..] = input else {
+        return None;
+    };
+    let body = match rest {
+        [0xFF, 0xFE, body @ ..] => body,
+        _ => {
+            return Some(Err(Error::with_position(
+                ErrorKind::Encoding,
+                "invalid text storage header",
+                3,
+            )));
+        }
+    };
+    Some(match mode {
+        0 => encoding::decode_utf16(body, HEADER_LEN, |pair| {
+            xor_scramble(u16::from_le_bytes(pair))
+        }),
+        1 => encoding::decode_utf16(body, HEADER_LEN, |pair| swap_bits(u16::from_le_bytes(pair))),
+        2 => decompress(body, max_len),
+        _ => Err(Error::with_position(
+            ErrorKind::Encoding,
+            format!("unsupported text storage mode {mode}"),
+            2,
+        )),
+    })
+}
+
+/// Upper bound on how many bytes zlib inflates a single input byte into.
+const MAX_INFLATE_RATIO: u64 = 1032;
+
+/// Inflates compressed text, failing before inflating anything if the header
+/// announces more than `max_len` bytes.
+fn decompress(body: &[u8], max_len: Option<usize>) -> Result<String> {
+    let sizes_end = HEADER_LEN + 16;
+    if body.len() < 16 {
+        return Err(Error::with_position(
+            ErrorKind::Encoding,
+            "truncated compressed text header",
+            HEADER_LEN + body.len(),
+        ));
+    }
+    let compressed = u64::from_le_bytes(body[..8].try_into().unwrap());
+    let uncompressed = u64::from_le_bytes(body[8..16].try_into().unwrap());
+    let data = &body[16..];
+    if compressed > data.len() as u64 {
+        return Err(Error::with_position(
+            ErrorKind::Encoding,
+            "truncated compressed text",
+            sizes_end,
+        ));
+    }
+    if compressed < data.len() as u64 {
+        return Err(Error::with_position(
+            ErrorKind::Encoding,
+            "compressed text length does not match its header",
+            HEADER_LEN,
+        ));
+    }
+    if !uncompressed.is_multiple_of(2) || uncompressed > compressed * MAX_INFLATE_RATIO {
+        return Err(Error::with_position(
+            ErrorKind::Encoding,
+            "invalid uncompressed text length in header",
+            HEADER_LEN + 8,
+        ));
+    }
+    parser::check_input_len(usize::try_from(uncompressed).unwrap_or(usize::MAX), max_len)?;
+
+    // Inflate at most one byte more than announced, so that a stream that
+    // expands further is caught without holding all of it in memory.
+    let mut text = Vec::new();
+    ZlibDecoder::new(data)
+        .take(uncompressed + 1)
+        .read_to_end(&mut text)
+        .map_err(|err| {
+            Error::with_position(
+                ErrorKind::Encoding,
+                format!("invalid compressed text: {err}"),
+                sizes_end,
+            )
+        })?;
+    if text.len() as u64 != uncompressed {
+        return Err(Error::with_position(
+            ErrorKind::Encoding,
+            "compressed text length does not match its header",
+            HEADER_LEN + 8,
+        ));
+    }
+    encoding::decode_utf16(&text, 0, u16::from_le_bytes)
+}
+
+/// Writes the text produced by `render` to `writer`, applying the storage mode
+/// and encoding selected in `options`.
+pub(crate) fn write_text<W, F>(mut writer: W, options: &SerializeOptions, render: F) -> Result<()>
+where
+    W: io::Write,
+    F: FnOnce(&mut dyn fmt::Write) -> Result<()>,
+{
+    match options.storage {
+        Storage::Plain => {
+            let mut output = EncodedWriter::new(writer, options.encoding);
+            if options.write_bom {
+                output.write_bom()?;
+            }
+            render(&mut output).map_err(|err| output.take_error_or(err))
+        }
+        Storage::XorScrambled => write_scrambled(writer, 0, xor_scramble, render),
+        Storage::BitSwapped => write_scrambled(writer, 1, swap_bits, render),
+        Storage::Compressed => {
+            let mut text = Vec::new();
+            {
+                let mut output = EncodedWriter::new(&mut text, Encoding::Utf16Le);
+                render(&mut output).map_err(|err| output.take_error_or(err))?;
+            }
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            io::Write::write_all(&mut encoder, &text).map_err(Error::io)?;
+            let compressed = encoder.finish().map_err(Error::io)?;
+
+            writer
+                .write_all(&header(2))
+                .and_then(|_| writer.write_all(&(compressed.len() as u64).to_le_bytes()))
+                .and_then(|_| writer.write_all(&(text.len() as u64).to_le_bytes()))
+                .and_then(|_| writer.write_all(&compressed))
+                .map_err(Error::io)
+        }
+    }
+}
+
+fn write_scrambled<W, F>(
+    mut writer: W,
+    mode: u8,
+    transform: fn(u16) -> u16,
+    render: F,
+) -> Result<()>
+where
+    W: io::Write,
+    F: FnOnce(&mut dyn fmt::Write) -> Result<()>,
+{
+    writer.write_all(&header(mode)).map_err(Error::io)?;
+    let mut output = EncodedWriter::scrambled(writer, transform);
+    render(&mut output).map_err(|err| output.take_error_or(err))
+}
@@ -1,10 +1,20 @@
+use std::cmp::Ordering;
 use std::fmt::{self, Write};
+use std::mem;
 
 use indexmap::IndexMap;
 
+use crate::encoding::Encoding;
+use crate::storage::Storage;
+
 /// Representation of TJS data values.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Values of any depth can be dropped without overflowing the stack. As
+/// `Value` implements `Drop`, the contents of a container are moved out with
+/// [`mem::take`] rather than by matching on the value itself.
+#[derive(Default)]
 pub enum Value {
+    #[default]
     Void,
     Null,
     Bool(bool),
@@ -21,6 +31,179 @@ pub enum Value {
 pub struct SerializeOptions {
     pub const_hint: bool,
     pub indent: Option<usize>,
+    /// Encoding used by the byte-oriented entry points (`to_vec*` and `to_writer*`).
+    pub encoding: Encoding,
+    /// Whether the byte-oriented entry points start the output with a byte order mark.
+    pub write_bom: bool,
+    /// Storage mode used by the byte-oriented entry points. Anything other than
+    /// [`Storage::Plain`] always produces UTF-16LE and ignores `encoding` and `write_bom`.
+    pub storage: Storage,
+    /// How real numbers are written.
+    pub float_style: FloatStyle,
+    /// Whether pretty-printed output is indented with one tab per level
+    /// instead of `indent` spaces.
+    pub use_tabs: bool,
+    /// Line ending used in pretty-printed output.
+    pub newline: Newline,
+    /// Whether pretty-printed arrays and dictionaries get a comma after their
+    /// last element.
+    pub trailing_comma: bool,
+    /// Whether dictionary keys are followed by a space before `=>`.
+    pub space_before_arrow: bool,
+    /// Quote character used for strings and dictionary keys.
+    pub quote: Quote,
+    /// Whether the `=>` of the entries of a pretty-printed dictionary are
+    /// lined up in one column.
+    pub align_arrows: bool,
+    /// Maximum number of bytes per line in an octet literal in pretty-printed
+    /// output. Longer literals are wrapped onto lines of their own.
+    pub octet_line_len: Option<usize>,
+    /// Target line width for pretty-printed output. Arrays and dictionaries
+    /// that fit in the rest of their line, including the comma after them,
+    /// are kept on it, written as in compact output; the others are broken
+    /// over several lines.
+    pub max_width: Option<usize>,
+    /// Whether empty arrays and dictionaries in pretty-printed output have
+    /// their closing bracket on a line of its own.
+    pub expand_empty: bool,
+    /// How octet literals are written.
+    pub octet_style: OctetStyle,
+    /// Order in which the entries of dictionaries are written.
+    pub key_order: KeyOrder,
+}
+
+/// Order in which the entries of dictionaries are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyOrder {
+    /// Insertion order.
+    #[default]
+    Preserve,
+    /// Keys compared character by character, so `"item10"` comes before
+    /// `"item2"`.
+    Lexicographic,
+    /// Runs of digits compared by their numeric value, so `"item2"` comes
+    /// before `"item10"`.
+    Natural,
+}
+
+impl KeyOrder {
+    fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            KeyOrder::Preserve => Ordering::Equal,
+            KeyOrder::Lexicographic => a.cmp(b),
+            KeyOrder::Natural => natural_cmp(a, b),
+        }
+    }
+}
+
+/// Compares `a` and `b`, treating each run of ASCII digits as one number.
+/// Numbers that only differ in leading zeros are ordered by their text.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn digit_run(text: &str) -> (&str, &str) {
+        text.split_at(
+            text.find(|ch: char| !ch.is_ascii_digit())
+                .unwrap_or(text.len()),
+        )
+    }
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let ((x, rest_a), (y, rest_b)) = (digit_run(a), digit_run(b));
+            let (x_value, y_value) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+            let order = x_value
+                .len()
+                .cmp(&y_value.len())
+                .then_with(|| x_value.cmp(y_value))
+                .then_with(|| x.cmp(y));
+            if order != Ordering::Equal {
+                return order;
+            }
+            (a, b) = (rest_a, rest_b);
+        } else if x != y {
+            return x.cmp(&y);
+        } else {
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+/// Line ending used in pretty-printed output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Newline {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl Newline {
+    fn as_str(self) -> &'static str {
+        match self {
+            Newline::Lf => "\n",
+            Newline::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Quote character used for strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quote {
+    #[default]
+    Double,
+    Single,
+}
+
+impl Quote {
+    fn as_char(self) -> char {
+        match self {
+            Quote::Double => '"',
+            Quote::Single => '\'',
+        }
+    }
+}
+
+/// Notation used for finite real numbers.
+///
+/// Every style except the Kirikiri ones writes the shortest text that reads
+/// back as exactly the same number, and writes `NaN` and infinities as `NaN`,
+/// `Infinity` and `-Infinity`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FloatStyle {
+    /// Plain decimal notation, such as `0.25` or `300000000000000000000.0`.
+    #[default]
+    Decimal,
+    /// Decimal notation, switching to an exponent such as `3e20` for
+    /// magnitudes below `lower` or at least `upper`.
+    Scientific { lower: f64, upper: f64 },
+    /// Hexadecimal notation such as `0x1.8p+3`.
+    HexFloat,
+    /// Hexadecimal notation with every digit, followed by the number in
+    /// `%.15g` notation in a comment, such as `0x1.8000000000000p3 /* 12 */`,
+    /// as written by Kirikiri's `saveStruct`. Infinities are written as
+    /// `+Infinity` and `-Infinity`.
+    ///
+    /// Like Kirikiri, this writes zero and subnormal numbers with a leading 1
+    /// and the exponent -1023, so they do not read back as the same number:
+    /// zero becomes `0x1.0000000000000p-1023 /* 0 */`, which is
+    /// 2<sup>-1023</sup>.
+    Kirikiri,
+    /// Like [`FloatStyle::Kirikiri`], but zero and subnormal numbers are
+    /// written with a leading 0, such as `0x0.0000000000000p0 /* 0 */`, so
+    /// that they read back as the same number.
+    KirikiriLossless,
+}
+
+/// Notation used for octet literals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OctetStyle {
+    /// Lower-case digits, such as `<% 0a ff %>`, and `<%%>` when empty.
+    #[default]
+    Standard,
+    /// Upper-case digits, such as `<% 0A FF %>`, and `<%  %>` when empty, as
+    /// written by Kirikiri's `saveStruct`.
+    Kirikiri,
 }
 
 impl Default for SerializeOptions {
@@ -28,6 +211,43 @@ impl Default for SerializeOptions {
         Self {
             const_hint: true,
             indent: None,
+            encoding: Encoding::Utf8,
+            write_bom: false,
+            storage: Storage::Plain,
+            float_style: FloatStyle::Decimal,
+            use_tabs: false,
+            newline: Newline::Lf,
+            trailing_comma: false,
+            space_before_arrow: true,
+            quote: Quote::Double,
+            align_arrows: false,
+            octet_line_len: None,
+            max_width: None,
+            expand_empty: false,
+            octet_style: OctetStyle::Standard,
+            key_order: KeyOrder::Preserve,
+        }
+    }
+}
+
+impl SerializeOptions {
+    /// Options that reproduce the output of Kirikiri's `Array.saveStruct` and
+    /// `Dictionary.saveStruct`: one space of indentation per level, CRLF line
+    /// endings, `(const)` hints, and UTF-16LE text with a byte order mark.
+    ///
+    /// The output is tested against a port of the engine's algorithm, not
+    /// against files saved by the engine.
+    pub fn kirikiri() -> Self {
+        Self {
+            const_hint: true,
+            indent: Some(1),
+            encoding: Encoding::Utf16Le,
+            write_bom: true,
+            float_style: FloatStyle::Kirikiri,
+            newline: Newline::CrLf,
+            expand_empty: true,
+            octet_style: OctetStyle::Kirikiri,
+            ..Self::default()
         }
     }
 }
@@ -41,19 +261,153 @@ impl Value {
         output
     }
 
+    /// Sorts the entries of this dictionary and of every dictionary nested in
+    /// this value by key, in [`KeyOrder::Lexicographic`] order.
+    pub fn sort_keys_recursive(&mut self) {
+        let mut pending = vec![self];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Array(items) => pending.extend(items.iter_mut()),
+                Value::Dictionary(entries) => {
+                    entries.sort_unstable_keys();
+                    pending.extend(entries.values_mut());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Moves the items of a container into `pending`, leaving it empty.
+    fn take_children(&mut self, pending: &mut Vec<Value>) {
+        match self {
+            Value::Array(items) => pending.append(items),
+            Value::Dictionary(entries) => pending.extend(entries.drain(..).map(|(_, value)| value)),
+            _ => {}
+        }
+    }
+
+    /// Names the type of the value, for error messages.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Value::Void => "void",
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Integer(_) => "integer",
+            Value::Real(_) => "real",
+            Value::String(_) => "string",
+            Value::Octet(_) => "octet",
+            Value::Array(_) => "array",
+            Value::Dictionary(_) => "dictionary",
+        }
+    }
+
     pub(crate) fn write_with_options<W: Write>(
         &self,
         writer: &mut W,
         options: &SerializeOptions,
     ) -> fmt::Result {
-        self.write_internal(writer, options, 0)
+        self.write_nested(writer, options, 0, 0)
+    }
+
+    /// Writes the value as if it were nested inside `depth` containers, with
+    /// the first character landing at `column` of the current line.
+    pub(crate) fn write_nested<W: Write>(
+        &self,
+        writer: &mut W,
+        options: &SerializeOptions,
+        depth: usize,
+        column: usize,
+    ) -> fmt::Result {
+        self.write_layout(&mut ColumnWriter::new(writer, column), options, depth)
     }
 
-    fn write_internal<W: Write>(
+    fn write_layout<W: Write>(
         &self,
+        writer: &mut ColumnWriter<W>,
+        options: &SerializeOptions,
+        depth: usize,
+    ) -> fmt::Result {
+        // Containers that fit on their line are written with these options.
+        let inline = match (options.indent, options.max_width) {
+            (Some(_), Some(width)) => Some((
+                width,
+                SerializeOptions {
+                    indent: None,
+                    ..options.clone()
+                },
+            )),
+            _ => None,
+        };
+        // Containers are tracked on an explicit stack so that deeply nested
+        // values cannot overflow the call stack.
+        let mut stack: Vec<Frame<'_>> = Vec::new();
+        // The value to write next, and the number of characters that follow
+        // it on its line.
+        let mut next = Some((self, 0));
+        loop {
+            if let Some((value, trailing)) = next.take() {
+                let level = depth + stack.len();
+                match &inline {
+                    Some((width, compact))
+                        if value.is_nonempty_container()
+                            && value
+                                .fits(compact, width.saturating_sub(writer.column + trailing)) =>
+                    {
+                        value.write_layout(writer, compact, level)?;
+                    }
+                    _ => value.write_open(writer, options, level, &mut stack)?,
+                }
+            }
+            let level = depth + stack.len();
+            let Some(frame) = stack.last_mut() else {
+                return Ok(());
+            };
+            let first = frame.first;
+            let key_width = frame.key_width;
+            let entry = frame.items.next();
+            frame.first = false;
+            match entry {
+                Some((key, value)) => {
+                    write_separator(writer, options, first, level)?;
+                    if let Some(key) = key {
+                        write_key(writer, options, key, key_width)?;
+                    }
+                    // Pretty-printed items are followed by a comma, and the
+                    // closing bracket goes on a line of its own.
+                    let comma = frame.items.remaining() > 0 || options.trailing_comma;
+                    next = Some((value, usize::from(comma)));
+                }
+                None => {
+                    stack.pop();
+                    write_close(writer, options, first, level)?;
+                }
+            }
+        }
+    }
+
+    fn is_nonempty_container(&self) -> bool {
+        match self {
+            Value::Array(items) => !items.is_empty(),
+            Value::Dictionary(entries) => !entries.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the value takes up at most `width` characters of a
+    /// single line when written with `options`.
+    fn fits(&self, options: &SerializeOptions, width: usize) -> bool {
+        self.write_nested(&mut WidthLimit(width), options, 0, 0)
+            .is_ok()
+    }
+
+    /// Writes a scalar, or the opening bracket of a container and pushes a
+    /// frame for its contents. `depth` is the number of enclosing containers.
+    fn write_open<'a, W: Write>(
+        &'a self,
         writer: &mut W,
         options: &SerializeOptions,
         depth: usize,
+        stack: &mut Vec<Frame<'a>>,
     ) -> fmt::Result {
         match self {
             Value::Void => writer.write_str("void"),
@@ -61,132 +415,650 @@ impl Value {
             Value::Bool(true) => writer.write_str("true"),
             Value::Bool(false) => writer.write_str("false"),
             Value::Integer(num) => write!(writer, "{}", num),
-            Value::Real(num) => {
-                if num.is_nan() {
-                    writer.write_str("NaN")
-                } else if num.is_infinite() {
-                    if num.is_sign_negative() {
-                        writer.write_str("-Infinity")
-                    } else {
-                        writer.write_str("Infinity")
-                    }
-                } else if num.fract() == 0.0 || num.fract() == 1.0 {
-                    write!(writer, "{}.0", num)
+            Value::Real(num) => write_real(writer, *num, options.float_style),
+            Value::String(text) => write_quoted(writer, text, options.quote),
+            Value::Octet(bytes) => write_octet(writer, bytes, options, depth),
+            Value::Array(items) => {
+                stack.push(Frame {
+                    items: Items::Array(items.iter()),
+                    first: true,
+                    key_width: 0,
+                });
+                write_open(writer, options, false)
+            }
+            Value::Dictionary(entries) => {
+                let key_width = if aligns_arrows(options) {
+                    entries
+                        .keys()
+                        .map(|key| key_width(key, options))
+                        .max()
+                        .unwrap_or(0)
                 } else {
-                    write!(writer, "{}", num)
-                }
+                    0
+                };
+                let items = if options.key_order == KeyOrder::Preserve {
+                    Items::Dictionary(entries.iter())
+                } else {
+                    let mut sorted: Vec<_> = entries.iter().collect();
+                    sorted.sort_by(|(a, _), (b, _)| options.key_order.compare(a, b));
+                    Items::Sorted(sorted.into_iter())
+                };
+                stack.push(Frame {
+                    items,
+                    first: true,
+                    key_width,
+                });
+                write_open(writer, options, true)
             }
-            Value::String(text) => write_string(writer, text),
-            Value::Octet(bytes) => write_octet(writer, bytes),
-            Value::Array(items) => {
-                if options.const_hint {
-                    writer.write_str("(const) ")?;
+        }
+    }
+}
+
+/// A container being written by [`Value::write_with_options`].
+struct Frame<'a> {
+    items: Items<'a>,
+    first: bool,
+    /// Width the keys are padded to when aligning arrows.
+    key_width: usize,
+}
+
+enum Items<'a> {
+    Array(std::slice::Iter<'a, Value>),
+    Dictionary(indexmap::map::Iter<'a, String, Value>),
+    Sorted(std::vec::IntoIter<(&'a String, &'a Value)>),
+}
+
+impl Items<'_> {
+    /// Returns the number of items that have not been written yet.
+    fn remaining(&self) -> usize {
+        match self {
+            Items::Array(items) => items.len(),
+            Items::Dictionary(entries) => entries.len(),
+            Items::Sorted(entries) => entries.len(),
+        }
+    }
+}
+
+impl<'a> Iterator for Items<'a> {
+    /// The key, for dictionary entries, and the value of the next item.
+    type Item = (Option<&'a String>, &'a Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Items::Array(items) => items.next().map(|item| (None, item)),
+            Items::Dictionary(entries) => entries.next().map(|(key, value)| (Some(key), value)),
+            Items::Sorted(entries) => entries.next().map(|(key, value)| (Some(key), value)),
+        }
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        // Values of any depth can be built in code or parsed without a depth
+        // limit, so nested values are flattened into a single list instead of
+        // dropped recursively.
+        let mut pending = Vec::new();
+        self.take_children(&mut pending);
+        while let Some(mut value) = pending.pop() {
+            value.take_children(&mut pending);
+        }
+    }
+}
+
+// `Debug`, `Clone` and `PartialEq` behave like derived implementations, but
+// walk nested containers with an explicit stack so that deeply nested values
+// cannot overflow the call stack.
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pretty = f.alternate();
+        let mut stack: Vec<(Items<'_>, bool)> = Vec::new();
+        let mut next = Some(self);
+        loop {
+            if let Some(value) = next.take() {
+                // Each open container indents its items by two levels: one
+                // for the variant and one for the list or map inside it.
+                let level = stack.len() * 2;
+                let (name, open, items) = match value {
+                    Value::Array(items) => ("Array(", '[', Items::Array(items.iter())),
+                    Value::Dictionary(entries) => {
+                        ("Dictionary(", '{', Items::Dictionary(entries.iter()))
+                    }
+                    scalar if pretty => {
+                        write!(DebugIndent(f, level), "{:#?}", DebugScalar(scalar))?;
+                        continue;
+                    }
+                    scalar => {
+                        fmt::Debug::fmt(&DebugScalar(scalar), f)?;
+                        continue;
+                    }
+                };
+                f.write_str(name)?;
+                if pretty {
+                    f.write_char('\n')?;
+                    write_debug_indent(f, level + 1)?;
                 }
-                writer.write_char('[')?;
-                if let Some(indent) = options.indent {
-                    if !items.is_empty() {
-                        writer.write_char('\n')?;
-                        for (idx, item) in items.iter().enumerate() {
-                            if idx > 0 {
-                                writer.write_str(",\n")?;
-                            }
-                            write_indent(writer, indent, depth + 1)?;
-                            item.write_internal(writer, options, depth + 1)?;
-                        }
-                        writer.write_char('\n')?;
-                        write_indent(writer, indent, depth)?;
+                f.write_char(open)?;
+                stack.push((items, true));
+            }
+            let level = stack.len() * 2;
+            let Some((items, first)) = stack.last_mut() else {
+                return Ok(());
+            };
+            let was_first = mem::replace(first, false);
+            match items.next() {
+                Some((key, value)) => {
+                    if pretty {
+                        f.write_str(if was_first { "\n" } else { ",\n" })?;
+                        write_debug_indent(f, level)?;
+                    } else if !was_first {
+                        f.write_str(", ")?;
                     }
-                } else {
-                    for (idx, item) in items.iter().enumerate() {
-                        if idx > 0 {
-                            writer.write_str(", ")?;
+                    if let Some(key) = key {
+                        fmt::Debug::fmt(key, f)?;
+                        f.write_str(": ")?;
+                    }
+                    next = Some(value);
+                }
+                None => {
+                    let close = match stack.pop() {
+                        Some((Items::Array(_), _)) => ']',
+                        _ => '}',
+                    };
+                    if pretty {
+                        if !was_first {
+                            f.write_str(",\n")?;
+                            write_debug_indent(f, level - 1)?;
                         }
-                        item.write_internal(writer, options, depth + 1)?;
+                        f.write_char(close)?;
+                        f.write_str(",\n")?;
+                        write_debug_indent(f, level - 2)?;
+                    } else {
+                        f.write_char(close)?;
                     }
+                    f.write_char(')')?;
                 }
-                writer.write_char(']')
             }
-            Value::Dictionary(entries) => {
-                if options.const_hint {
-                    writer.write_str("(const) ")?;
+        }
+    }
+}
+
+/// Formats a scalar [`Value`] the way a derived `Debug` would.
+struct DebugScalar<'a>(&'a Value);
+
+impl fmt::Debug for DebugScalar<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Value::Void => f.write_str("Void"),
+            Value::Null => f.write_str("Null"),
+            Value::Bool(v) => f.debug_tuple("Bool").field(v).finish(),
+            Value::Integer(v) => f.debug_tuple("Integer").field(v).finish(),
+            Value::Real(v) => f.debug_tuple("Real").field(v).finish(),
+            Value::String(v) => f.debug_tuple("String").field(v).finish(),
+            Value::Octet(v) => f.debug_tuple("Octet").field(v).finish(),
+            Value::Array(_) | Value::Dictionary(_) => unreachable!("not a scalar"),
+        }
+    }
+}
+
+/// Indents every line after the first by the given number of levels, as
+/// pretty-printed `Debug` output does for nested values.
+struct DebugIndent<'a, 'b>(&'a mut fmt::Formatter<'b>, usize);
+
+impl Write for DebugIndent<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (idx, line) in s.split('\n').enumerate() {
+            if idx > 0 {
+                self.0.write_char('\n')?;
+                write_debug_indent(self.0, self.1)?;
+            }
+            self.0.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_debug_indent(f: &mut fmt::Formatter<'_>, level: usize) -> fmt::Result {
+    (0..level).try_for_each(|_| f.write_str("    "))
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Self {
+        /// A container whose items are being cloned, together with the key
+        /// of the item being cloned when it is a dictionary.
+        enum Partial<'a> {
+            Array(Vec<Value>),
+            Dictionary(IndexMap<String, Value>, Option<&'a String>),
+        }
+
+        let mut stack: Vec<(Items<'_>, Partial<'_>)> = Vec::new();
+        let mut next = self;
+        loop {
+            let mut done = match next {
+                Value::Void => Some(Value::Void),
+                Value::Null => Some(Value::Null),
+                Value::Bool(v) => Some(Value::Bool(*v)),
+                Value::Integer(v) => Some(Value::Integer(*v)),
+                Value::Real(v) => Some(Value::Real(*v)),
+                Value::String(v) => Some(Value::String(v.clone())),
+                Value::Octet(v) => Some(Value::Octet(v.clone())),
+                Value::Array(items) => {
+                    let partial = Partial::Array(Vec::with_capacity(items.len()));
+                    stack.push((Items::Array(items.iter()), partial));
+                    None
                 }
-                writer.write_str("%[")?;
-                if let Some(indent) = options.indent {
-                    if !entries.is_empty() {
-                        writer.write_char('\n')?;
-                        for (idx, (key, value)) in entries.iter().enumerate() {
-                            if idx > 0 {
-                                writer.write_str(",\n")?;
-                            }
-                            write_indent(writer, indent, depth + 1)?;
-                            write_string(writer, key)?;
-                            writer.write_str(" => ")?;
-                            value.write_internal(writer, options, depth + 1)?;
-                        }
-                        writer.write_char('\n')?;
-                        write_indent(writer, indent, depth)?;
+                Value::Dictionary(entries) => {
+                    let partial = Partial::Dictionary(IndexMap::with_capacity(entries.len()), None);
+                    stack.push((Items::Dictionary(entries.iter()), partial));
+                    None
+                }
+            };
+            // Adds finished values to their containers until one of them has
+            // another item to clone.
+            loop {
+                let Some((items, partial)) = stack.last_mut() else {
+                    return done.expect("the outermost value is finished");
+                };
+                match (partial, done.take()) {
+                    (Partial::Array(values), Some(value)) => values.push(value),
+                    (Partial::Dictionary(entries, key), Some(value)) => {
+                        let key = key.take().expect("a key for every dictionary value");
+                        entries.insert(key.clone(), value);
                     }
-                } else {
-                    for (idx, (key, value)) in entries.iter().enumerate() {
-                        if idx > 0 {
-                            writer.write_str(", ")?;
-                        }
-                        write_string(writer, key)?;
-                        writer.write_str(" => ")?;
-                        value.write_internal(writer, options, depth + 1)?;
+                    (_, None) => {}
+                }
+                if let Some((key, value)) = items.next() {
+                    if let Some((_, Partial::Dictionary(_, pending))) = stack.last_mut() {
+                        *pending = key;
                     }
+                    next = value;
+                    break;
+                }
+                done = match stack.pop() {
+                    Some((_, Partial::Array(values))) => Some(Value::Array(values)),
+                    Some((_, Partial::Dictionary(entries, _))) => Some(Value::Dictionary(entries)),
+                    None => None,
+                };
+            }
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        let mut pending = vec![(self, other)];
+        while let Some(pair) = pending.pop() {
+            let equal = match pair {
+                (Value::Void, Value::Void) | (Value::Null, Value::Null) => true,
+                (Value::Bool(a), Value::Bool(b)) => a == b,
+                (Value::Integer(a), Value::Integer(b)) => a == b,
+                (Value::Real(a), Value::Real(b)) => a == b,
+                (Value::String(a), Value::String(b)) => a == b,
+                (Value::Octet(a), Value::Octet(b)) => a == b,
+                (Value::Array(a), Value::Array(b)) => {
+                    pending.extend(a.iter().zip(b));
+                    a.len() == b.len()
                 }
-                writer.write_char(']')
+                // Like `IndexMap`, dictionaries compare equal regardless of
+                // the order of their entries.
+                (Value::Dictionary(a), Value::Dictionary(b)) => {
+                    a.len() == b.len()
+                        && a.iter().all(|(key, a)| match b.get(key) {
+                            Some(b) => {
+                                pending.push((a, b));
+                                true
+                            }
+                            None => false,
+                        })
+                }
+                _ => false,
+            };
+            if !equal {
+                return false;
             }
         }
+        true
+    }
+}
+
+/// Keeps track of the column the next character is written at.
+pub(crate) struct ColumnWriter<W> {
+    inner: W,
+    pub(crate) column: usize,
+}
+
+impl<W: Write> ColumnWriter<W> {
+    pub(crate) fn new(inner: W, column: usize) -> Self {
+        Self { inner, column }
+    }
+}
+
+impl<W: Write> Write for ColumnWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.column = match s.rfind('\n') {
+            Some(idx) => s[idx + 1..].chars().count(),
+            None => self.column + s.chars().count(),
+        };
+        self.inner.write_str(s)
+    }
+}
+
+/// Fails once more than the given number of characters, or a line break, is
+/// written.
+struct WidthLimit(usize);
+
+impl Write for WidthLimit {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.contains('\n') {
+            return Err(fmt::Error);
+        }
+        self.0 = self.0.checked_sub(s.chars().count()).ok_or(fmt::Error)?;
+        Ok(())
+    }
+}
+
+/// Returns `true` if a container cannot be laid out before all of its
+/// content is known.
+pub(crate) fn needs_lookahead(options: &SerializeOptions) -> bool {
+    options.indent.is_some() && (options.align_arrows || options.max_width.is_some())
+}
+
+fn write_indent<W: Write>(writer: &mut W, options: &SerializeOptions, depth: usize) -> fmt::Result {
+    match options.indent {
+        Some(_) if options.use_tabs => (0..depth).try_for_each(|_| writer.write_char('\t')),
+        Some(indent) => (0..indent * depth).try_for_each(|_| writer.write_char(' ')),
+        None => Ok(()),
+    }
+}
+
+/// Returns `true` if dictionary entries are lined up at their `=>`.
+pub(crate) fn aligns_arrows(options: &SerializeOptions) -> bool {
+    options.align_arrows && options.indent.is_some()
+}
+
+/// Writes the opening bracket of an array or dictionary.
+pub(crate) fn write_open<W: Write>(
+    writer: &mut W,
+    options: &SerializeOptions,
+    dictionary: bool,
+) -> fmt::Result {
+    if options.const_hint {
+        writer.write_str("(const) ")?;
+    }
+    writer.write_str(if dictionary { "%[" } else { "[" })
+}
+
+/// Writes what goes in front of an element of the container at `depth`
+/// (counting from 1 for the outermost one).
+pub(crate) fn write_separator<W: Write>(
+    writer: &mut W,
+    options: &SerializeOptions,
+    first: bool,
+    depth: usize,
+) -> fmt::Result {
+    if options.indent.is_some() {
+        if !first {
+            writer.write_char(',')?;
+        }
+        writer.write_str(options.newline.as_str())?;
+        write_indent(writer, options, depth)
+    } else if !first {
+        writer.write_str(", ")
+    } else {
+        Ok(())
     }
 }
 
-fn write_indent<W: Write>(writer: &mut W, indent: usize, depth: usize) -> fmt::Result {
-    for _ in 0..(indent * depth) {
-        writer.write_char(' ')?;
+/// Returns the number of characters `key` takes up when written.
+fn key_width(key: &str, options: &SerializeOptions) -> usize {
+    let mut quoted = String::new();
+    // Writing to a `String` cannot fail.
+    let _ = write_quoted(&mut quoted, key, options.quote);
+    quoted.chars().count()
+}
+
+/// Writes the key of a dictionary entry, up to its value, padding it to
+/// `width` characters.
+pub(crate) fn write_key<W: Write>(
+    writer: &mut W,
+    options: &SerializeOptions,
+    key: &str,
+    width: usize,
+) -> fmt::Result {
+    write_quoted(writer, key, options.quote)?;
+    if width > 0 {
+        let padding = width.saturating_sub(key_width(key, options));
+        (0..padding).try_for_each(|_| writer.write_char(' '))?;
     }
-    Ok(())
+    writer.write_str(if options.space_before_arrow {
+        " => "
+    } else {
+        "=> "
+    })
 }
 
-fn write_string<W: Write>(writer: &mut W, text: &str) -> fmt::Result {
-    writer.write_char('"')?;
-    for ch in text.chars() {
+/// Writes the closing bracket of the container at `depth`.
+pub(crate) fn write_close<W: Write>(
+    writer: &mut W,
+    options: &SerializeOptions,
+    empty: bool,
+    depth: usize,
+) -> fmt::Result {
+    if options.indent.is_some() && (!empty || options.expand_empty) {
+        if options.trailing_comma && !empty {
+            writer.write_char(',')?;
+        }
+        writer.write_str(options.newline.as_str())?;
+        write_indent(writer, options, depth - 1)?;
+    }
+    writer.write_char(']')
+}
+
+pub(crate) fn write_string<W: Write>(writer: &mut W, text: &str) -> fmt::Result {
+    write_quoted(writer, text, Quote::Double)
+}
+
+pub(crate) fn write_quoted<W: Write>(writer: &mut W, text: &str, quote: Quote) -> fmt::Result {
+    let quote = quote.as_char();
+    writer.write_char(quote)?;
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
         match ch {
             '\\' => writer.write_str("\\\\")?,
-            '"' => writer.write_str("\\\"")?,
+            ch if ch == quote => {
+                writer.write_char('\\')?;
+                writer.write_char(ch)?;
+            }
             '\n' => writer.write_str("\\n")?,
             '\r' => writer.write_str("\\r")?,
             '\t' => writer.write_str("\\t")?,
+            '\x07' => writer.write_str("\\a")?,
             '\x08' => writer.write_str("\\b")?,
+            '\x0b' => writer.write_str("\\v")?,
             '\x0c' => writer.write_str("\\f")?,
+            // Control characters all lie below U+00A0.
             ch if ch.is_control() => {
                 let code = ch as u32;
-                if code <= 0xFF {
-                    write!(writer, "\\x{:02x}", code)?;
+                if chars.peek().is_some_and(char::is_ascii_hexdigit) {
+                    // `\x` takes up to four digits, so pad it to keep the
+                    // next character out of the escape.
+                    write!(writer, "\\x{:04x}", code)?;
                 } else {
-                    write!(writer, "\\u{:04x}", code)?;
+                    write!(writer, "\\x{:02x}", code)?;
                 }
             }
             ch => writer.write_char(ch)?,
         }
     }
-    writer.write_char('"')
+    writer.write_char(quote)
 }
 
-fn write_octet<W: Write>(writer: &mut W, bytes: &[u8]) -> fmt::Result {
-    writer.write_str("<%")?;
-    if !bytes.is_empty() {
-        writer.write_char(' ')?;
+pub(crate) fn write_real<W: Write>(writer: &mut W, num: f64, style: FloatStyle) -> fmt::Result {
+    write_real_digits(writer, num, num, style)
+}
+
+/// Writes an `f32` like [`write_real`], except that decimal notation uses the
+/// shortest digits that read back as the same `f32`, so that `0.1f32` is
+/// written as `0.1` rather than `0.10000000149011612`.
+pub(crate) fn write_f32<W: Write>(writer: &mut W, num: f32, style: FloatStyle) -> fmt::Result {
+    write_real_digits(writer, f64::from(num), num, style)
+}
+
+/// Writes `num`, taking the digits of decimal notation from `digits`, which
+/// holds the same number at the width it was given in.
+fn write_real_digits<W, D>(writer: &mut W, num: f64, digits: D, style: FloatStyle) -> fmt::Result
+where
+    W: Write,
+    D: fmt::Display + fmt::LowerExp,
+{
+    if matches!(style, FloatStyle::Kirikiri | FloatStyle::KirikiriLossless) {
+        return write_kirikiri_real(writer, num, style == FloatStyle::KirikiriLossless);
     }
-    for (idx, byte) in bytes.iter().enumerate() {
-        if idx > 0 {
-            writer.write_char(' ')?;
+    if num.is_nan() {
+        return writer.write_str("NaN");
+    } else if num.is_infinite() {
+        return writer.write_str(if num < 0.0 { "-Infinity" } else { "Infinity" });
+    }
+    match style {
+        FloatStyle::Scientific { lower, upper }
+            if num != 0.0 && (num.abs() < lower || num.abs() >= upper) =>
+        {
+            write!(writer, "{:e}", digits)
         }
-        write!(writer, "{:02x}", byte)?;
+        FloatStyle::HexFloat => write_hex_float(writer, num),
+        _ if num.fract() == 0.0 => write!(writer, "{}.0", digits),
+        _ => write!(writer, "{}", digits),
     }
-    if !bytes.is_empty() {
-        writer.write_char(' ')?;
+}
+
+/// Writes a finite `num` as `0x1.xxxp+N`, or `0x0.xxxp-1022` when subnormal.
+fn write_hex_float<W: Write>(writer: &mut W, num: f64) -> fmt::Result {
+    const MANTISSA_BITS: u32 = 52;
+    let bits = num.to_bits();
+    let biased = ((bits >> MANTISSA_BITS) & 0x7ff) as i32;
+    let mut mantissa = bits & ((1 << MANTISSA_BITS) - 1);
+    if num.is_sign_negative() {
+        writer.write_char('-')?;
+    }
+    if biased == 0 && mantissa == 0 {
+        return writer.write_str("0x0p+0");
+    }
+    let (lead, exponent) = if biased == 0 {
+        (0, -1022)
+    } else {
+        (1, biased - 1023)
+    };
+    write!(writer, "0x{lead}")?;
+    if mantissa != 0 {
+        // 13 hex digits hold the 52 bits; trailing zero digits are dropped.
+        let mut digits = 13;
+        while mantissa & 0xf == 0 {
+            mantissa >>= 4;
+            digits -= 1;
+        }
+        write!(writer, ".{mantissa:0digits$x}")?;
+    }
+    write!(writer, "p{exponent:+}")
+}
+
+/// Writes `num` the way Kirikiri does, as a hexadecimal number with all 13
+/// digits of the mantissa followed by a `%.15g` comment. Zero and subnormal
+/// numbers get a leading 0 if `lossless` is set.
+fn write_kirikiri_real<W: Write>(writer: &mut W, num: f64, lossless: bool) -> fmt::Result {
+    const MANTISSA_BITS: u32 = 52;
+    if !num.is_finite() {
+        let text = match num {
+            _ if num.is_nan() => "NaN",
+            _ if num < 0.0 => "-Infinity",
+            _ => "+Infinity",
+        };
+        return write!(writer, "{text} /* {text} */");
+    }
+    let bits = num.to_bits();
+    let biased = ((bits >> MANTISSA_BITS) & 0x7ff) as i32;
+    let mantissa = bits & ((1 << MANTISSA_BITS) - 1);
+    if num.is_sign_negative() {
+        writer.write_char('-')?;
+    }
+    let (lead, exponent) = match (biased, mantissa) {
+        (0, 0) if lossless => (0, 0),
+        (0, _) if lossless => (0, -1022),
+        _ => (1, biased - 1023),
+    };
+    write!(writer, "0x{lead}.{mantissa:013X}p{exponent} /* ")?;
+    write_general(writer, num)?;
+    writer.write_str(" */")
+}
+
+/// Writes a finite `num` like C's `%.15g`.
+fn write_general<W: Write>(writer: &mut W, num: f64) -> fmt::Result {
+    const PRECISION: i32 = 15;
+    fn trim_zeros(text: &str) -> &str {
+        if text.contains('.') {
+            text.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            text
+        }
+    }
+    let scientific = format!("{:.*e}", PRECISION as usize - 1, num);
+    let (mantissa, exponent) = scientific.split_once('e').ok_or(fmt::Error)?;
+    let exponent: i32 = exponent.parse().map_err(|_| fmt::Error)?;
+    if !(-4..PRECISION).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        write!(
+            writer,
+            "{}e{sign}{:02}",
+            trim_zeros(mantissa),
+            exponent.unsigned_abs()
+        )
+    } else {
+        let fixed = format!("{:.*}", (PRECISION - 1 - exponent) as usize, num);
+        writer.write_str(trim_zeros(&fixed))
+    }
+}
+
+/// Writes an octet literal. In pretty-printed output, it is wrapped onto
+/// lines indented one level deeper than `depth` when it is longer than
+/// [`SerializeOptions::octet_line_len`].
+pub(crate) fn write_octet<W: Write>(
+    writer: &mut W,
+    bytes: &[u8],
+    options: &SerializeOptions,
+    depth: usize,
+) -> fmt::Result {
+    let write_bytes = |writer: &mut W, bytes: &[u8]| {
+        for (idx, byte) in bytes.iter().enumerate() {
+            if idx > 0 {
+                writer.write_char(' ')?;
+            }
+            match options.octet_style {
+                OctetStyle::Standard => write!(writer, "{:02x}", byte)?,
+                OctetStyle::Kirikiri => write!(writer, "{:02X}", byte)?,
+            }
+        }
+        Ok(())
+    };
+    writer.write_str("<%")?;
+    match options.octet_line_len {
+        Some(line_len) if options.indent.is_some() && line_len > 0 && bytes.len() > line_len => {
+            for line in bytes.chunks(line_len) {
+                writer.write_str(options.newline.as_str())?;
+                write_indent(writer, options, depth + 1)?;
+                write_bytes(writer, line)?;
+            }
+            writer.write_str(options.newline.as_str())?;
+            write_indent(writer, options, depth)?;
+        }
+        _ if bytes.is_empty() => {
+            if options.octet_style == OctetStyle::Kirikiri {
+                writer.write_str("  ")?;
+            }
+        }
+        _ => {
+            writer.write_char(' ')?;
+            write_bytes(writer, bytes)?;
+            writer.write_char(' ')?;
+        }
     }
     writer.write_str("%>")
 }
@@ -0,0 +1,115 @@
+#!/usr/bin/env python3
+"""Writes the golden files used by the `kirikiri_preset_matches_save_struct`
+test.
+
+The files are not produced by the engine itself, so parity with Kirikiri is
+unverified until they are replaced with files the engine saved. This script follows the
+algorithm of Kirikiri's `Array.saveStruct` and `Dictionary.saveStruct`
+(`SaveStructuredData` in tjsArray.cpp and tjsDictionary.cpp, and
+`TJSRealToHexString` in tjsUtils.cpp), independently of the Rust serializer,
+so that a change to the serializer cannot silently update its own expected
+output.
+
+Run it from this directory after changing the data below.
+"""
+
+import math
+import struct
+
+CRLF = "\r\n"
+
+
+def real(num):
+    """Formats a real like TJSRealToHexString, plus the `%.15g` comment."""
+    if math.isnan(num):
+        return "NaN /* NaN */"
+    if math.isinf(num):
+        text = "-Infinity" if num < 0 else "+Infinity"
+        return f"{text} /* {text} */"
+    bits = struct.unpack("<Q", struct.pack("<d", num))[0]
+    sign = "-" if bits >> 63 else ""
+    biased = (bits >> 52) & 0x7FF
+    mantissa = bits & ((1 << 52) - 1)
+    # The leading digit is always 1 and the exponent is always the biased
+    # exponent less 1023, even for zero and subnormal numbers.
+    return f"{sign}0x1.{mantissa:013X}p{biased - 1023} /* {'%.15g' % num} */"
+
+
+def string(text):
+    escapes = {"\\": "\\\\", '"': '\\"', "\n": "\\n", "\r": "\\r", "\t": "\\t"}
+    assert all(ch >= " " or ch in escapes for ch in text)
+    return '"' + "".join(escapes.get(ch, ch) for ch in text) + '"'
+
+
+def octet(data):
+    return "<% " + " ".join(f"{byte:02X}" for byte in data) + " %>"
+
+
+def value(item, indent):
+    if item is None:
+        return "void"
+    if isinstance(item, bool):
+        raise TypeError("booleans are integers in TJS2")
+    if isinstance(item, int):
+        return str(item)
+    if isinstance(item, float):
+        return real(item)
+    if isinstance(item, str):
+        return string(item)
+    if isinstance(item, bytes):
+        return octet(item)
+    if isinstance(item, list):
+        return container("(const) [", [value(x, indent + 1) for x in item], indent)
+    if isinstance(item, Dict):
+        entries = [f"{string(k)} => {value(v, indent + 1)}" for k, v in item.entries]
+        return container("(const) %[", entries, indent)
+    raise TypeError(type(item))
+
+
+def container(open_bracket, items, indent):
+    inner = " " * (indent + 1)
+    body = "".join(("," if i else "") + CRLF + inner + item for i, item in enumerate(items))
+    # Empty containers still have their closing bracket on a line of its own.
+    return open_bracket + body + CRLF + " " * indent + "]"
+
+
+class Dict:
+    """Keeps entries in the order given, as Kirikiri does for these files."""
+
+    def __init__(self, *entries):
+        self.entries = entries
+
+
+DICTIONARY = Dict(
+    ("name", 'Alice "A"'),
+    ("level", 3),
+    ("ratio", 0.1),
+    ("tags", ["a", -2.0]),
+    ("zero", 0.0),
+    ("empty", []),
+    ("data", bytes([0x01, 0xAB, 0xFF])),
+    ("blank", b""),
+    ("none", None),
+    ("nested", Dict()),
+)
+
+ARRAY = [
+    1.5,
+    1e100,
+    1.0 / 3.0,
+    123456789.125,
+    2.5e-7,
+    0.0,
+    "文字列",
+    [1, Dict(("x", 0))],
+]
+
+
+def main():
+    for name, data in [("kirikiri_dictionary.tjs", DICTIONARY), ("kirikiri_array.tjs", ARRAY)]:
+        with open(name, "wb") as file:
+            file.write(b"\xff\xfe" + value(data, 0).encode("utf-16-le"))
+
+
+if __name__ == "__main__":
+    main()